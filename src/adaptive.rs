@@ -0,0 +1,113 @@
+use crate::film::Film;
+
+/// Adaptive sampling settings, pixels get more samples while their relative error
+/// is above `threshold`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Adaptive {
+    pub threshold: f64,
+    /// samples of the first pass, used to estimate the pixels variance
+    pub min_spp: usize,
+    pub max_spp: usize,
+}
+
+/// returns the number of samples to add to each pixel during the next pass
+///
+/// `pass_budget` is split between unconverged pixels proportionally to their relative error.
+/// Converged pixels and pixels that reached `max_spp` get no sample.
+pub(crate) fn allocate_pass(film: &Film, adaptive: &Adaptive, pass_budget: usize) -> Vec<usize> {
+    let errors: Vec<f64> = film
+        .pixels()
+        .iter()
+        .map(|pixel| {
+            let error = pixel.relative_error();
+            if error > adaptive.threshold && pixel.sample_count < adaptive.max_spp {
+                error
+            } else {
+                0.
+            }
+        })
+        .collect();
+    let error_sum: f64 = errors.iter().sum();
+    if error_sum <= 0. {
+        return vec![0; errors.len()];
+    }
+    film.pixels()
+        .iter()
+        .zip(errors.iter())
+        .map(|(pixel, error)| {
+            let samples = (pass_budget as f64 * error / error_sum).ceil() as usize;
+            samples.min(adaptive.max_spp - pixel.sample_count.min(adaptive.max_spp))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::RRgb;
+    use crate::film::FilmTile;
+    use crate::filter::Filter;
+    use crate::ray::RT;
+    use crate::tile::Tile;
+
+    /// film of one row of pixels, each getting gray samples of the given luminances
+    fn film_with_samples(pixels: &[Vec<f64>]) -> Film {
+        let width = pixels.len() as u32;
+        let bounds = Tile {
+            x: 0,
+            y: 0,
+            width,
+            height: 1,
+        };
+        let mut film_tile = FilmTile::new(bounds, false, None);
+        for (x, luminances) in pixels.iter().enumerate() {
+            for &luminance in luminances.iter() {
+                let color = RRgb::new(luminance, luminance, luminance);
+                let position = [x as RT + 0.5, 0.5];
+                film_tile.add_sample(
+                    (x as u32, 0),
+                    position,
+                    &color,
+                    1.,
+                    None,
+                    &Filter::default(),
+                );
+            }
+        }
+        let mut film = Film::new(width, 1, vec![], false);
+        film.merge_tile(&film_tile);
+        film
+    }
+
+    /// luminances alternating between 0 and 200
+    fn noisy(sample_count: usize) -> Vec<f64> {
+        (0..sample_count).map(|i| (i % 2) as f64 * 200.).collect()
+    }
+
+    const ADAPTIVE: Adaptive = Adaptive {
+        threshold: 0.01,
+        min_spp: 4,
+        max_spp: 16,
+    };
+
+    #[test]
+    fn constant_pixel_stops_at_min_spp() {
+        let film = film_with_samples(&[vec![100.; 4], noisy(4)]);
+        let samples = allocate_pass(&film, &ADAPTIVE, 8);
+        assert_eq!(samples[0], 0);
+        assert!(samples[1] > 0);
+    }
+
+    #[test]
+    fn noisy_pixel_gets_samples_up_to_max_spp() {
+        let film = film_with_samples(&[noisy(8), noisy(15), noisy(16)]);
+        let samples = allocate_pass(&film, &ADAPTIVE, 1000);
+        assert_eq!(samples, vec![8, 1, 0]);
+    }
+
+    #[test]
+    fn converged_film_gets_no_sample() {
+        let film = film_with_samples(&[vec![100.; 4], vec![50.; 4]]);
+        assert_eq!(allocate_pass(&film, &ADAPTIVE, 1000), vec![0, 0]);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::cli::parse_triplet;
+use crate::output::suffixed_file_path;
+use crate::ray::RT;
+use nalgebra::{Point3, Vector3};
+use std::ops::{Add, Mul, Sub};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Camera settings that can change from one frame to another.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct CameraPose {
+    pub look_from: Point3<RT>,
+    pub look_at: Point3<RT>,
+    pub vfov: RT,
+}
+
+impl CameraPose {
+    /// checks that the pose and `vup` define a camera, interpolation may overshoot the keyframes
+    pub(crate) fn check(&self, vup: &Vector3<RT>) -> anyhow::Result<()> {
+        let view_direction = self.look_at - self.look_from;
+        if !(self.vfov > 0. && self.vfov < 180.) {
+            Err(anyhow::anyhow!(
+                "vertical fov {} should be in (0, 180) degrees",
+                self.vfov
+            ))
+        } else if view_direction.norm() == 0. {
+            Err(anyhow::anyhow!(
+                "look from and look at should be different points"
+            ))
+        } else if vup.cross(&view_direction).norm() == 0. {
+            Err(anyhow::anyhow!(
+                "vup should not be null or parallel to the view direction"
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Keyframe {
+    pub frame: u32,
+    pub pose: CameraPose,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Interpolation {
+    Linear,
+    CatmullRom,
+}
+
+impl FromStr for Interpolation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Interpolation::Linear),
+            "catmull-rom" => Ok(Interpolation::CatmullRom),
+            _ => Err(anyhow::anyhow!(
+                "invalid interpolation {}, expected linear or catmull-rom",
+                s
+            )),
+        }
+    }
+}
+
+/// Keyframed camera path, sorted by frame.
+#[derive(Clone, Debug)]
+pub(crate) struct CameraPath {
+    keyframes: Vec<Keyframe>,
+    interpolation: Interpolation,
+}
+
+impl CameraPath {
+    pub(crate) fn new(
+        mut keyframes: Vec<Keyframe>,
+        interpolation: Interpolation,
+    ) -> anyhow::Result<Self> {
+        if keyframes.is_empty() {
+            return Err(anyhow::anyhow!("camera path should have >= 1 keyframe"));
+        }
+        keyframes.sort_by_key(|keyframe| keyframe.frame);
+        if keyframes.windows(2).any(|k| k[0].frame == k[1].frame) {
+            return Err(anyhow::anyhow!(
+                "camera path keyframes should have distinct frames"
+            ));
+        }
+        Ok(CameraPath {
+            keyframes,
+            interpolation,
+        })
+    }
+
+    /// reads keyframes from a text file
+    ///
+    /// One keyframe per line: `FRAME LOOK_FROM LOOK_AT VFOV`, e.g. `1 0,5,5 0,0,-1 90`.
+    /// Empty lines and lines starting with `#` are ignored.
+    pub(crate) fn open<P: AsRef<Path>>(
+        path: P,
+        interpolation: Interpolation,
+    ) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let mut keyframes = vec![];
+        for (line_index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [frame, look_from, look_at, vfov] => {
+                    let [fx, fy, fz] = parse_triplet(look_from)?;
+                    let [ax, ay, az] = parse_triplet(look_at)?;
+                    let pose = CameraPose {
+                        look_from: Point3::new(fx, fy, fz),
+                        look_at: Point3::new(ax, ay, az),
+                        vfov: vfov.parse::<RT>()?,
+                    };
+                    if pose.vfov <= 0. || pose.look_from == pose.look_at {
+                        return Err(anyhow::anyhow!(
+                            "invalid keyframe line {}, vfov should be > 0 and look from and look at should be different points",
+                            line_index + 1
+                        ));
+                    }
+                    keyframes.push(Keyframe {
+                        frame: frame.parse::<u32>()?,
+                        pose,
+                    })
+                }
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "invalid keyframe line {}, expected FRAME LOOK_FROM LOOK_AT VFOV",
+                        line_index + 1
+                    ))
+                }
+            }
+        }
+        CameraPath::new(keyframes, interpolation)
+    }
+
+    pub(crate) fn first_frame(&self) -> u32 {
+        self.keyframes[0].frame
+    }
+
+    pub(crate) fn last_frame(&self) -> u32 {
+        self.keyframes[self.keyframes.len() - 1].frame
+    }
+
+    /// returns the interpolated camera pose, clamped to the first and last keyframes
+    pub(crate) fn pose_at(&self, frame: u32) -> CameraPose {
+        let last = self.keyframes.len() - 1;
+        if frame <= self.first_frame() {
+            return self.keyframes[0].pose;
+        }
+        if frame >= self.last_frame() {
+            return self.keyframes[last].pose;
+        }
+        // keyframes[i1].frame < frame <= keyframes[i2].frame
+        let i2 = self
+            .keyframes
+            .iter()
+            .position(|keyframe| keyframe.frame >= frame)
+            .unwrap_or(last);
+        let i1 = i2 - 1;
+        let (k1, k2) = (&self.keyframes[i1], &self.keyframes[i2]);
+        let t = (frame - k1.frame) as RT / (k2.frame - k1.frame) as RT;
+        match self.interpolation {
+            Interpolation::Linear => CameraPose {
+                look_from: k1.pose.look_from + (k2.pose.look_from - k1.pose.look_from).scale(t),
+                look_at: k1.pose.look_at + (k2.pose.look_at - k1.pose.look_at).scale(t),
+                vfov: k1.pose.vfov + (k2.pose.vfov - k1.pose.vfov) * t,
+            },
+            Interpolation::CatmullRom => {
+                // end keyframes are duplicated so that the curve goes through them
+                let k0 = &self.keyframes[i1.saturating_sub(1)];
+                let k3 = &self.keyframes[(i2 + 1).min(last)];
+                CameraPose {
+                    look_from: Point3::from(catmull_rom(
+                        k0.pose.look_from.coords,
+                        k1.pose.look_from.coords,
+                        k2.pose.look_from.coords,
+                        k3.pose.look_from.coords,
+                        t,
+                    )),
+                    look_at: Point3::from(catmull_rom(
+                        k0.pose.look_at.coords,
+                        k1.pose.look_at.coords,
+                        k2.pose.look_at.coords,
+                        k3.pose.look_at.coords,
+                        t,
+                    )),
+                    vfov: catmull_rom(k0.pose.vfov, k1.pose.vfov, k2.pose.vfov, k3.pose.vfov, t),
+                }
+            }
+        }
+    }
+}
+
+/// uniform Catmull-Rom spline between p1 (t = 0) and p2 (t = 1)
+fn catmull_rom<T>(p0: T, p1: T, p2: T, p3: T, t: RT) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<RT, Output = T>,
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.
+        + (p2 - p0) * t
+        + (p0 * 2. - p1 * 5. + p2 * 4. - p3) * t2
+        + (p1 * 3. - p0 - p2 * 3. + p3) * t3)
+        * 0.5
+}
+
+/// `out.png` -> `out_0001.png`
+pub(crate) fn frame_file_path(output_file_path: &str, frame: u32) -> String {
+    suffixed_file_path(output_file_path, &format!("{:04}", frame))
+}
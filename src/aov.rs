@@ -0,0 +1,372 @@
+use crate::color::RRgb;
+use crate::film::{read_bytes, Film};
+use crate::material::Material;
+use crate::output::{suffixed_file_path, BitDepth};
+use crate::ray::{RayHit, RT};
+use crate::sampler::hash;
+use image::{DynamicImage, Rgb};
+use nalgebra::{Point3, Vector3};
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// Arbitrary output variable, a property of the first surface seen through each pixel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Aov {
+    /// distance along the view direction
+    Depth,
+    /// normal facing the camera
+    Normal,
+    Albedo,
+    /// world position
+    Position,
+    /// hash of the material parameters, on 24 bits
+    MaterialId,
+    /// bvh node index of the object
+    ObjectId,
+    /// material ids in random colors, to look at them
+    MaterialIdPreview,
+    /// object ids in random colors, to look at them
+    ObjectIdPreview,
+}
+
+/// serialized by index, new AOVs go at the end
+const AOVS: [Aov; 8] = [
+    Aov::Depth,
+    Aov::Normal,
+    Aov::Albedo,
+    Aov::Position,
+    Aov::MaterialId,
+    Aov::ObjectId,
+    Aov::MaterialIdPreview,
+    Aov::ObjectIdPreview,
+];
+
+/// ids are taken modulo `ID_COUNT`, id + 1 fits the 24 bits of an 8 bits RGB pixel
+const ID_COUNT: u64 = 0xff_ffff;
+
+impl Aov {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Position => "position",
+            Aov::MaterialId => "material-id",
+            Aov::ObjectId => "object-id",
+            Aov::MaterialIdPreview => "material-id-preview",
+            Aov::ObjectIdPreview => "object-id-preview",
+        }
+    }
+
+    fn index(&self) -> u8 {
+        AOVS.iter().position(|aov| aov == self).unwrap_or_default() as u8
+    }
+}
+
+impl FromStr for Aov {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AOVS.iter()
+            .find(|aov| aov.name() == s)
+            .copied()
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "invalid aov {}, expected depth, normal, albedo, position, material-id, object-id, material-id-preview or object-id-preview",
+                    s
+                )
+            })
+    }
+}
+
+/// Surface seen at the first hit of a camera ray.
+#[derive(Clone, Debug)]
+pub(crate) struct AovSample {
+    pub depth: RT,
+    pub normal: Vector3<RT>,
+    pub albedo: RRgb,
+    pub position: Point3<RT>,
+    pub material_id: u64,
+    pub object_id: u64,
+    /// holdouts hide what is behind them but are transparent
+    pub holdout: bool,
+}
+
+impl AovSample {
+    /// the depth depends on the camera, it is left to the caller
+    pub(crate) fn new(ray_hit: &RayHit) -> Self {
+        AovSample {
+            depth: 0.,
+            normal: ray_hit.normal,
+            albedo: ray_hit.material.albedo(),
+            position: ray_hit.point,
+            material_id: material_id(&ray_hit.material),
+            object_id: ray_hit.node_index as u64,
+            holdout: matches!(ray_hit.material, Material::Holdout),
+        }
+    }
+}
+
+/// identifies a material by its parameters, below `ID_COUNT`
+fn material_id(material: &Material) -> u64 {
+    let bits = |color: &RRgb| color.to_array().map(f64::to_bits);
+    let id = match material {
+        Material::Dieletric(dieletric) => {
+            let indices = dieletric.refraction_index.fraunhofer_indices();
+            hash(
+                &[
+                    &[0],
+                    &indices.map(f64::to_bits)[..],
+                    &bits(&dieletric.absorption)[..],
+                ]
+                .concat(),
+            )
+        }
+        Material::Lambertian(lambertian) => hash(&[&[1], &bits(&lambertian.albedo)[..]].concat()),
+        Material::Metal(metal) => hash(&[&[2], &bits(&metal.albedo)[..]].concat()),
+        Material::Light(light) => hash(
+            &[
+                &[3],
+                &bits(&light.emitted)[..],
+                &[light.texture.is_some() as u64, light.two_sided as u64],
+            ]
+            .concat(),
+        ),
+        Material::Holdout => hash(&[4]),
+    };
+    id % ID_COUNT
+}
+
+/// AOV samples accumulated by a pixel, averaged over the samples that hit a surface.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct AovPixel {
+    depth_sum: f64,
+    normal_sum: [f64; 3],
+    albedo_sum: [f64; 3],
+    position_sum: [f64; 3],
+    hit_count: usize,
+    /// ids of the first surface hit, ids cannot be averaged
+    material_id: u64,
+    object_id: u64,
+}
+
+impl AovPixel {
+    pub(crate) fn add_sample(&mut self, sample: &AovSample) {
+        if self.hit_count == 0 {
+            self.material_id = sample.material_id;
+            self.object_id = sample.object_id;
+        }
+        self.depth_sum += sample.depth as f64;
+        for (i, albedo) in sample.albedo.to_array().iter().enumerate() {
+            self.normal_sum[i] += sample.normal[i] as f64;
+            self.albedo_sum[i] += albedo;
+            self.position_sum[i] += sample.position[i] as f64;
+        }
+        self.hit_count += 1;
+    }
+
+    pub(crate) fn merge(&mut self, other: &AovPixel) {
+        if self.hit_count == 0 {
+            self.material_id = other.material_id;
+            self.object_id = other.object_id;
+        }
+        self.depth_sum += other.depth_sum;
+        for i in 0..3 {
+            self.normal_sum[i] += other.normal_sum[i];
+            self.albedo_sum[i] += other.albedo_sum[i];
+            self.position_sum[i] += other.position_sum[i];
+        }
+        self.hit_count += other.hit_count;
+    }
+
+    /// mean value of the pixel, none if no sample hit a surface or for ids, see `id`
+    pub(crate) fn value(&self, aov: Aov) -> Option<[f64; 3]> {
+        if self.hit_count == 0 {
+            return None;
+        }
+        let mean = |sum: [f64; 3]| sum.map(|s| s / self.hit_count as f64);
+        Some(match aov {
+            Aov::Depth => mean([self.depth_sum; 3]),
+            Aov::Normal => {
+                let [x, y, z] = mean(self.normal_sum);
+                let norm = (x * x + y * y + z * z).sqrt().max(f64::EPSILON);
+                [x / norm, y / norm, z / norm]
+            }
+            Aov::Albedo => mean(self.albedo_sum),
+            Aov::Position => mean(self.position_sum),
+            Aov::MaterialId | Aov::ObjectId | Aov::MaterialIdPreview | Aov::ObjectIdPreview => {
+                return None
+            }
+        })
+    }
+
+    /// material or object id of the first surface hit, ids cannot be averaged
+    pub(crate) fn id(&self, aov: Aov) -> Option<u64> {
+        if self.hit_count == 0 {
+            return None;
+        }
+        match aov {
+            Aov::MaterialId | Aov::MaterialIdPreview => Some(self.material_id),
+            Aov::ObjectId | Aov::ObjectIdPreview => Some(self.object_id),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        for value in [self.depth_sum]
+            .iter()
+            .chain(&self.normal_sum)
+            .chain(&self.albedo_sum)
+            .chain(&self.position_sum)
+        {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for value in &[self.hit_count as u64, self.material_id, self.object_id] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut sums = [0f64; 10];
+        for value in sums.iter_mut() {
+            *value = f64::from_le_bytes(read_bytes(reader)?);
+        }
+        let [depth_sum, nx, ny, nz, ar, ag, ab, px, py, pz] = sums;
+        Ok(AovPixel {
+            depth_sum,
+            normal_sum: [nx, ny, nz],
+            albedo_sum: [ar, ag, ab],
+            position_sum: [px, py, pz],
+            hit_count: u64::from_le_bytes(read_bytes(reader)?) as usize,
+            material_id: u64::from_le_bytes(read_bytes(reader)?),
+            object_id: u64::from_le_bytes(read_bytes(reader)?),
+        })
+    }
+}
+
+pub(crate) fn write_aov_kinds<W: Write>(aovs: &[Aov], writer: &mut W) -> std::io::Result<()> {
+    writer.write_all(&[aovs.len() as u8])?;
+    for aov in aovs {
+        writer.write_all(&[aov.index()])?;
+    }
+    Ok(())
+}
+
+pub(crate) fn read_aov_kinds<R: Read>(reader: &mut R) -> std::io::Result<Vec<Aov>> {
+    let [count] = read_bytes(reader)?;
+    (0..count)
+        .map(|_| {
+            let [index] = read_bytes(reader)?;
+            AOVS.get(index as usize)
+                .copied()
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid aov"))
+        })
+        .collect()
+}
+
+/// returns the image of an AOV and a description of the range of its values
+///
+/// Depth and position are normalized to their range over the image, normals are mapped
+/// from [-1, 1] to [0, 255] and id previews get a random color. Pixels without hit are black.
+pub(crate) fn aov_image(
+    film: &Film,
+    aov: Aov,
+    bit_depth: BitDepth,
+) -> (DynamicImage, Option<String>) {
+    if let Aov::MaterialId | Aov::ObjectId = aov {
+        return (id_image(film, aov), None);
+    }
+    let values: Vec<Option<[f64; 3]>> = film.aovs().iter().map(|p| p.value(aov)).collect();
+    // only meaningful for depth and position
+    let (min, max) = values.iter().flatten().fold(
+        ([f64::INFINITY; 3], [f64::NEG_INFINITY; 3]),
+        |(min, max), value| {
+            (
+                [0, 1, 2].map(|i| min[i].min(value[i])),
+                [0, 1, 2].map(|i| max[i].max(value[i])),
+            )
+        },
+    );
+    let normalize = |value: f64, i: usize| {
+        if max[i] > min[i] {
+            (value - min[i]) / (max[i] - min[i])
+        } else {
+            1.
+        }
+    };
+    let to_color = |index: usize| -> Option<[f64; 3]> {
+        let value = values[index];
+        match aov {
+            Aov::Depth => value.map(|value| [normalize(value[0], 0); 3]),
+            Aov::Position => value.map(|value| [0, 1, 2].map(|i| normalize(value[i], i))),
+            Aov::Normal => value.map(|value| value.map(|v| v * 0.5 + 0.5)),
+            Aov::Albedo => value,
+            Aov::MaterialIdPreview | Aov::ObjectIdPreview => film.aovs()[index].id(aov).map(|id| {
+                let h = hash(&[id]);
+                [0, 1, 2].map(|i| ((h >> (8 * i)) & 0xff) as f64 / 255.)
+            }),
+            Aov::MaterialId | Aov::ObjectId => None,
+        }
+    };
+    let color = |index: usize, max: f64| match to_color(index) {
+        Some(color) => color.map(|c| (c.clamp(0., 1.) * max).round()),
+        None => [0.; 3],
+    };
+    let img = match bit_depth {
+        BitDepth::Eight => DynamicImage::ImageRgb8(
+            film.to_image_with(|index| Rgb(color(index, 255.).map(|c| c as u8))),
+        ),
+        BitDepth::Sixteen => DynamicImage::ImageRgb16(
+            film.to_image_with(|index| Rgb(color(index, 65535.).map(|c| c as u16))),
+        ),
+    };
+    let range = match aov {
+        Aov::Depth if min[0] <= max[0] => Some(format!("{} {}", min[0], max[0])),
+        Aov::Position if min[0] <= max[0] => Some(format!(
+            "{},{},{} {},{},{}",
+            min[0], min[1], min[2], max[0], max[1], max[2]
+        )),
+        _ => None,
+    };
+    (img, range)
+}
+
+/// returns the image of the integer ids of a film, whatever the bit depth
+///
+/// Each pixel holds id + 1 over 24 bits, red being the most significant byte, 0 where nothing
+/// was hit.
+fn id_image(film: &Film, aov: Aov) -> DynamicImage {
+    DynamicImage::ImageRgb8(film.to_image_with(|index| Rgb(encode_id(film.aovs()[index].id(aov)))))
+}
+
+/// id + 1 over 24 bits, red first, 0 for no id
+fn encode_id(id: Option<u64>) -> [u8; 3] {
+    let value = id.map_or(0, |id| id % ID_COUNT + 1);
+    [16, 8, 0].map(|shift| (value >> shift) as u8)
+}
+
+/// `out.png` -> `out_depth.png`
+pub(crate) fn aov_file_path(output_file_path: &str, aov: Aov) -> String {
+    suffixed_file_path(output_file_path, aov.name())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_id(rgb: [u8; 3]) -> Option<u64> {
+        let value = rgb
+            .iter()
+            .fold(0u64, |value, &byte| value << 8 | byte as u64);
+        value.checked_sub(1)
+    }
+
+    #[test]
+    fn ids_are_written_as_integers() {
+        for id in [0, 1, 255, 256, 70_000, ID_COUNT - 1] {
+            assert_eq!(decode_id(encode_id(Some(id))), Some(id));
+        }
+        assert_eq!(encode_id(None), [0, 0, 0]);
+    }
+}
@@ -0,0 +1,168 @@
+use crate::ray::RT;
+use crate::sampler::hash;
+use image::GenericImageView;
+use std::fmt;
+use std::str::FromStr;
+
+/// Lens aperture shape, determines the shape of defocused highlights (bokeh).
+#[derive(Clone, Debug, Default)]
+pub(crate) enum Aperture {
+    /// circular aperture
+    #[default]
+    Disc,
+    /// regular polygon inscribed in the unit disc, one vertex per blade
+    Polygon { blades: usize, rotation: RT },
+    /// ring between `inner_ratio` and the unit circle (catadioptric lens)
+    Annulus { inner_ratio: RT },
+    /// arbitrary shape defined by an image, brighter pixels let more light through
+    Mask(ApertureMask),
+}
+
+impl Aperture {
+    /// maps a uniform sample of the unit square to a point of the aperture, within the unit disc
+    pub(crate) fn sample(&self, u: [RT; 2]) -> [RT; 2] {
+        match self {
+            Aperture::Disc => sample_annulus(0., u),
+            Aperture::Polygon { blades, rotation } => sample_polygon(*blades, *rotation, u),
+            Aperture::Annulus { inner_ratio } => sample_annulus(*inner_ratio, u),
+            Aperture::Mask(mask) => mask.sample(u),
+        }
+    }
+}
+
+fn sample_polygon(blades: usize, rotation: RT, [u, v]: [RT; 2]) -> [RT; 2] {
+    // the polygon is split into `blades` triangles of equal area sharing the center
+    let scaled_u = u * blades as RT;
+    let blade = (scaled_u as usize).min(blades - 1);
+    let su = scaled_u - blade as RT; // remaining uniform sample within the blade
+    let blade_angle = 2. * std::f32::consts::PI as RT / blades as RT;
+    let a0 = rotation + blade as RT * blade_angle;
+    let a1 = a0 + blade_angle;
+    // uniform sampling of the triangle (center, vertex a0, vertex a1)
+    let su = su.sqrt();
+    let (w0, w1) = (su * (1. - v), su * v);
+    [w0 * a0.cos() + w1 * a1.cos(), w0 * a0.sin() + w1 * a1.sin()]
+}
+
+fn sample_annulus(inner_ratio: RT, [u, v]: [RT; 2]) -> [RT; 2] {
+    let inner_squared = inner_ratio * inner_ratio;
+    let r = (inner_squared + u * (1. - inner_squared)).sqrt();
+    let theta = 2. * std::f32::consts::PI as RT * v;
+    [r * theta.cos(), r * theta.sin()]
+}
+
+#[derive(Clone)]
+pub(crate) struct ApertureMask {
+    width: u32,
+    height: u32,
+    /// cumulative distribution of the pixel intensities, row major
+    cdf: Vec<RT>,
+}
+
+impl fmt::Debug for ApertureMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the cdf is summarized, it can be several thousand values long
+        let cdf_bits: Vec<u64> = self.cdf.iter().map(|c| c.to_bits() as u64).collect();
+        f.debug_struct("ApertureMask")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("cdf_hash", &hash(&cdf_bits))
+            .finish()
+    }
+}
+
+impl ApertureMask {
+    pub(crate) fn open(path: &str) -> anyhow::Result<Self> {
+        let img = image::open(path)?;
+        let (width, height) = img.dimensions();
+        let luma = img.to_luma8();
+        // integer sums are exact, a float sum drifts on large masks
+        let sums: Vec<u64> = luma
+            .pixels()
+            .scan(0u64, |total, pixel| {
+                *total += pixel[0] as u64;
+                Some(*total)
+            })
+            .collect();
+        let total = sums.last().copied().unwrap_or_default();
+        if total == 0 {
+            return Err(anyhow::anyhow!(
+                "aperture mask {} is completely black",
+                path
+            ));
+        }
+        let mut cdf: Vec<RT> = sums
+            .iter()
+            .map(|&sum| (sum as f64 / total as f64) as RT)
+            .collect();
+        // every u in [0, 1) falls on a pixel
+        if let Some(last) = cdf.last_mut() {
+            *last = 1.;
+        }
+        Ok(ApertureMask { width, height, cdf })
+    }
+
+    fn sample(&self, [u, v]: [RT; 2]) -> [RT; 2] {
+        let index = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .min(self.cdf.len() - 1);
+        // u is reused to jitter within the pixel once rescaled to the pixel cdf range
+        let cdf_start = if index > 0 { self.cdf[index - 1] } else { 0. };
+        let cdf_range = self.cdf[index] - cdf_start;
+        let du = if cdf_range > 0. {
+            ((u - cdf_start) / cdf_range).clamp(0., 1.)
+        } else {
+            0.5
+        };
+        let x = (index as u32 % self.width) as RT + du;
+        let y = (index as u32 / self.width) as RT + v;
+        // the mask is fitted into the [-1, 1] square, keeping its aspect ratio
+        let size = self.width.max(self.height) as RT;
+        let dx = (2. * x - self.width as RT) / size;
+        let dy = (self.height as RT - 2. * y) / size; // image y axis points downward
+        [dx, dy]
+    }
+}
+
+impl FromStr for Aperture {
+    type Err = anyhow::Error;
+
+    /// `disc`, `polygon:BLADES[:ROTATION]`, `annulus:INNER_RATIO` or `mask:PATH`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let kind = parts.next().unwrap_or_default();
+        let args = parts.next();
+        match (kind, args) {
+            ("disc", None) => Ok(Aperture::Disc),
+            ("polygon", Some(args)) => {
+                let mut args = args.split(':');
+                let blades = args.next().unwrap_or_default().parse::<usize>()?;
+                let rotation = match args.next() {
+                    Some(rotation) => rotation.parse::<RT>()?,
+                    None => 0.,
+                };
+                if blades < 3 {
+                    return Err(anyhow::anyhow!("aperture polygon should have >= 3 blades"));
+                }
+                Ok(Aperture::Polygon {
+                    blades,
+                    rotation: rotation.to_radians(),
+                })
+            }
+            ("annulus", Some(inner_ratio)) => {
+                let inner_ratio = inner_ratio.parse::<RT>()?;
+                if (0. ..1.).contains(&inner_ratio) {
+                    Ok(Aperture::Annulus { inner_ratio })
+                } else {
+                    Err(anyhow::anyhow!("annulus inner ratio should be in [0, 1)"))
+                }
+            }
+            ("mask", Some(path)) => Ok(Aperture::Mask(ApertureMask::open(path)?)),
+            _ => Err(anyhow::anyhow!(
+                "invalid aperture shape {}, expected disc, polygon:BLADES[:ROTATION], annulus:INNER_RATIO or mask:PATH",
+                s
+            )),
+        }
+    }
+}
@@ -1,8 +1,20 @@
-use crate::ray::{Ray, RT};
+use crate::aperture::Aperture;
+use crate::ray::{shoot_ray, Ray, Target, RT};
+use bvh::bvh::BVH;
 use nalgebra::{Point3, Vector3};
-use rand::prelude::{Distribution, ThreadRng};
-use rand_distr::UnitDisc;
 
+/// How the focal plane distance is chosen.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Focus {
+    /// focus on the look at point
+    LookAt,
+    /// explicit distance from the camera
+    Distance(RT),
+    /// focus on whatever is seen through the given pixel (x, y), image center if none
+    Auto(Option<(u32, u32)>),
+}
+
+#[derive(Debug)]
 pub(crate) struct Camera {
     origin: Point3<RT>,
     lower_left_corner: Point3<RT>,
@@ -10,7 +22,9 @@ pub(crate) struct Camera {
     vertical: Vector3<RT>,
     u: Vector3<RT>,
     v: Vector3<RT>,
+    w: Vector3<RT>,
     lens_radius: RT,
+    aperture_shape: Aperture,
 }
 
 impl Camera {
@@ -45,12 +59,37 @@ impl Camera {
             lower_left_corner,
             u,
             v,
+            w,
             lens_radius,
+            aperture_shape: Aperture::default(),
+        }
+    }
+
+    pub(crate) fn with_aperture_shape(self, aperture_shape: Aperture) -> Self {
+        Camera {
+            aperture_shape,
+            ..self
         }
     }
 
-    pub(crate) fn get_ray(&self, s: RT, t: RT, thread_rng: &mut ThreadRng) -> Ray<RT> {
-        let [dx_offset, dy_offset]: [RT; 2] = UnitDisc.sample(thread_rng);
+    /// returns the distance between the camera and the first object seen through (s, t)
+    ///
+    /// The distance is measured along the view direction, as expected by `focus_dist`.
+    pub(crate) fn focus_distance(&self, s: RT, t: RT, world: &[Target], bvh: &BVH) -> Option<RT> {
+        let direction = self.lower_left_corner + self.horizontal.scale(s) + self.vertical.scale(t)
+            - self.origin;
+        let ray = Ray::new(self.origin, direction);
+        shoot_ray(&ray, world, bvh, 0.01, RT::INFINITY).map(|ray_hit| self.depth(&ray_hit.point))
+    }
+
+    /// distance from the camera to `point` along the view direction
+    pub(crate) fn depth(&self, point: &Point3<RT>) -> RT {
+        (point - self.origin).dot(&-self.w)
+    }
+
+    /// returns the ray going through (s, t) from the `lens` sample of the unit square
+    pub(crate) fn get_ray(&self, s: RT, t: RT, lens: [RT; 2]) -> Ray<RT> {
+        let [dx_offset, dy_offset] = self.aperture_shape.sample(lens);
         let offset =
             self.u.scale(dx_offset * self.lens_radius) + self.v.scale(dy_offset * self.lens_radius);
         let direction = self.lower_left_corner + self.horizontal.scale(s) + self.vertical.scale(t)
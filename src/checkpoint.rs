@@ -0,0 +1,116 @@
+use crate::film::{read_bytes, Film};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+const MAGIC: &[u8; 8] = b"RRAYCKPT";
+const VERSION: u32 = 10;
+
+/// Identifies the render a checkpoint belongs to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct CheckpointHeader {
+    pub seed: u64,
+    /// fingerprint of the settings the image depends on (size, camera, sampler...)
+    pub config_hash: u64,
+    /// fingerprint of the scene description
+    pub scene_hash: u64,
+    /// number of passes already rendered
+    pub pass_index: u64,
+    /// share of the samples rendered, shard 0 of 1 when the render is not sharded
+    pub shard_index: u64,
+    pub shard_count: u64,
+    /// samples per pixel of the whole render, shards split the sample indices according to it
+    pub sample_per_pixel: u64,
+    /// strata of the sampler, 0 if the samples are not stratified
+    pub sampler_strata: u64,
+}
+
+/// Render state needed to resume a render with more samples.
+///
+/// The sampler is a deterministic function of the seed, the pixel and the sample index:
+/// the per pixel sample counts of the film are enough to carry on where the render stopped.
+pub(crate) struct Checkpoint {
+    pub header: CheckpointHeader,
+    pub film: Film,
+}
+
+impl Checkpoint {
+    /// writes the checkpoint through a temporary file, a killed render never leaves a truncated checkpoint
+    pub(crate) fn save(path: &str, header: &CheckpointHeader, film: &Film) -> anyhow::Result<()> {
+        let partial_path = format!("{}.partial", path);
+        {
+            let mut writer = BufWriter::new(File::create(&partial_path)?);
+            writer.write_all(MAGIC)?;
+            writer.write_all(&VERSION.to_le_bytes())?;
+            for value in &[
+                header.seed,
+                header.config_hash,
+                header.scene_hash,
+                header.pass_index,
+                header.shard_index,
+                header.shard_count,
+                header.sample_per_pixel,
+                header.sampler_strata,
+            ] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+            film.write(&mut writer)?;
+            writer.flush()?;
+        }
+        std::fs::rename(&partial_path, path)?;
+        Ok(())
+    }
+
+    pub(crate) fn open(path: &str) -> anyhow::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(anyhow::anyhow!("{} is not a rray checkpoint", path));
+        }
+        let version = u32::from_le_bytes(read_bytes(&mut reader)?);
+        if version != VERSION {
+            return Err(anyhow::anyhow!(
+                "unsupported checkpoint version {} in {}",
+                version,
+                path
+            ));
+        }
+        let seed = u64::from_le_bytes(read_bytes(&mut reader)?);
+        let config_hash = u64::from_le_bytes(read_bytes(&mut reader)?);
+        let scene_hash = u64::from_le_bytes(read_bytes(&mut reader)?);
+        let pass_index = u64::from_le_bytes(read_bytes(&mut reader)?);
+        let shard_index = u64::from_le_bytes(read_bytes(&mut reader)?);
+        let shard_count = u64::from_le_bytes(read_bytes(&mut reader)?);
+        let sample_per_pixel = u64::from_le_bytes(read_bytes(&mut reader)?);
+        let sampler_strata = u64::from_le_bytes(read_bytes(&mut reader)?);
+        if shard_index >= shard_count {
+            return Err(anyhow::anyhow!(
+                "invalid shard {}/{} in {}",
+                shard_index,
+                shard_count,
+                path
+            ));
+        }
+        let film = Film::read(&mut reader)?;
+        Ok(Checkpoint {
+            header: CheckpointHeader {
+                seed,
+                config_hash,
+                scene_hash,
+                pass_index,
+                shard_index,
+                shard_count,
+                sample_per_pixel,
+                sampler_strata,
+            },
+            film,
+        })
+    }
+}
+
+/// stable 64 bits hash of a description (FNV-1a)
+pub(crate) fn fingerprint(description: &str) -> u64 {
+    description.bytes().fold(0xcbf2_9ce4_8422_2325, |h, byte| {
+        (h ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
@@ -1,5 +1,30 @@
+use crate::adaptive::Adaptive;
+use crate::animation::{CameraPath, CameraPose, Interpolation};
+use crate::aov::Aov;
+use crate::aperture::Aperture;
+use crate::camera::Focus;
+use crate::color::RRgb;
+use crate::filter::{Filter, FilterKind};
+use crate::ior::Ior;
+use crate::light::PunctualLight;
+use crate::material::Emission;
+use crate::output::{check_output_format, BitDepth};
 use crate::ray::RT;
-use clap::{App, Arg};
+use crate::render::{Crop, CropOutput, Progressive, Shard};
+use crate::sampler::SamplerKind;
+use crate::spectrum::Spectrum;
+use crate::texture::Texture;
+use crate::tile::TileOrder;
+use clap::{App, Arg, SubCommand};
+use image::ImageFormat;
+use nalgebra::{Point3, Vector3};
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+/// sample per pixel when rendering is only limited by time
+const TIME_LIMITED_MAX_SAMPLE_PER_PIXEL: usize = 65536;
+/// every sample is splatted on the pixels within the filter radius, in pixels
+const MAX_FILTER_RADIUS: RT = 64.;
 
 pub(crate) fn get_app() -> App<'static, 'static> {
     App::new(crate_name!())
@@ -14,6 +39,114 @@ pub(crate) fn get_app() -> App<'static, 'static> {
                 .help("sample per pixel (>=1)")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("sampler")
+                .long("sampler")
+                .value_name("SAMPLER")
+                .required(false)
+                .help("pixel sampler: independent, stratified, halton or sobol (default)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("adaptive_threshold")
+                .long("adaptive-threshold")
+                .value_name("THRESHOLD")
+                .required(false)
+                .help("enable adaptive sampling, pixels are sampled until their relative error is below THRESHOLD (e.g. 0.01), keeping SPP on average")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("min_spp")
+                .long("min-spp")
+                .value_name("MIN_SPP")
+                .required(false)
+                .help("adaptive sampling: samples per pixel used to estimate the variance (>=2, default 16)")
+                .takes_value(true)
+                .requires("adaptive_threshold"),
+        )
+        .arg(
+            Arg::with_name("max_spp")
+                .long("max-spp")
+                .value_name("MAX_SPP")
+                .required(false)
+                .help("adaptive sampling: max samples per pixel (default 4 * SPP)")
+                .takes_value(true)
+                .requires("adaptive_threshold"),
+        )
+        .arg(
+            Arg::with_name("pass_spp")
+                .long("pass-spp")
+                .value_name("PASS_SPP")
+                .required(false)
+                .help("progressive rendering: render in passes of PASS_SPP samples per pixel, writing the current image after each pass (adaptive sampling uses its own passes)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("snapshot_interval")
+                .long("snapshot-interval")
+                .value_name("DURATION")
+                .required(false)
+                .help("progressive rendering: min delay between two intermediate images, e.g. 30s, 5m (seconds by default)")
+                .takes_value(true)
+                .requires("pass_spp"),
+        )
+        .arg(
+            Arg::with_name("time_limit")
+                .long("time-limit")
+                .value_name("DURATION")
+                .required(false)
+                .help("keep adding sample passes until DURATION (e.g. 90s, 5m) is spent, SPP becomes a max (65536 by default)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .required(false)
+                .help("seed of the scene generation and of the sampler (default 0)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("checkpoint")
+                .long("checkpoint")
+                .value_name("FILE")
+                .required(false)
+                .help("save the render state to FILE regularly and at the end, so that it can be resumed")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("checkpoint_interval")
+                .long("checkpoint-interval")
+                .value_name("DURATION")
+                .required(false)
+                .help("min delay between two checkpoints (default 5m)")
+                .takes_value(true)
+                .requires("checkpoint"),
+        )
+        .arg(
+            Arg::with_name("resume")
+                .long("resume")
+                .value_name("FILE")
+                .required(false)
+                .help("resume the render saved in checkpoint FILE, e.g. with more samples per pixel")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("filter")
+                .long("filter")
+                .value_name("FILTER")
+                .required(false)
+                .help("pixel reconstruction filter: box (default), tent, gaussian, mitchell or lanczos")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("filter_radius")
+                .long("filter-radius")
+                .value_name("PIXELS")
+                .required(false)
+                .help("filter radius (default 0.5 box, 1 tent, 1.5 gaussian, 2 mitchell and lanczos)")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("max_depth")
                 .short("md")
@@ -30,6 +163,50 @@ pub(crate) fn get_app() -> App<'static, 'static> {
                 .help("image width (pixels)")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("image_height")
+                .long("height")
+                .value_name("IMAGE_HEIGHT")
+                .required(false)
+                .help("image height (pixels), derived from the aspect ratio by default")
+                .takes_value(true)
+                .conflicts_with("aspect_ratio"),
+        )
+        .arg(
+            Arg::with_name("aspect_ratio")
+                .long("aspect-ratio")
+                .value_name("ASPECT_RATIO")
+                .required(false)
+                .help("image aspect ratio (width / height)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("look_from")
+                .long("look-from")
+                .value_name("X,Y,Z")
+                .required(false)
+                .help("camera position")
+                .takes_value(true)
+                .allow_hyphen_values(true),
+        )
+        .arg(
+            Arg::with_name("look_at")
+                .long("look-at")
+                .value_name("X,Y,Z")
+                .required(false)
+                .help("point the camera looks at")
+                .takes_value(true)
+                .allow_hyphen_values(true),
+        )
+        .arg(
+            Arg::with_name("vup")
+                .long("vup")
+                .value_name("X,Y,Z")
+                .required(false)
+                .help("camera up direction")
+                .takes_value(true)
+                .allow_hyphen_values(true),
+        )
         .arg(
             Arg::with_name("vertical_fov")
                 .long("vfov")
@@ -46,6 +223,58 @@ pub(crate) fn get_app() -> App<'static, 'static> {
                 .help("aperture: 0.0 means everything is in focus")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("focus_dist")
+                .long("focus-dist")
+                .value_name("FOCUS_DIST")
+                .required(false)
+                .help("distance to the focal plane, defaults to the look at point distance")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("autofocus")
+                .long("autofocus")
+                .value_name("X,Y")
+                .required(false)
+                .help("focus on the object seen through pixel X,Y (image center by default)")
+                .takes_value(true)
+                .min_values(0)
+                .max_values(1)
+                .conflicts_with("focus_dist"),
+        )
+        .arg(
+            Arg::with_name("bokeh")
+                .long("bokeh")
+                .value_name("SHAPE")
+                .required(false)
+                .help("aperture shape: disc, polygon:BLADES[:ROTATION], annulus:INNER_RATIO or mask:PATH")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("camera_path")
+                .long("camera-path")
+                .value_name("FILE")
+                .required(false)
+                .help("keyframed camera path, one `FRAME LOOK_FROM LOOK_AT VFOV` per line")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("interpolation")
+                .long("interpolation")
+                .value_name("INTERPOLATION")
+                .required(false)
+                .help("camera path interpolation: linear or catmull-rom (default)")
+                .takes_value(true)
+                .requires("camera_path"),
+        )
+        .arg(
+            Arg::with_name("frames")
+                .long("frames")
+                .value_name("START..END")
+                .required(false)
+                .help("render frames START to END (inclusive) to numbered outputs, defaults to the camera path keyframes range")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("output")
                 .short("o")
@@ -55,42 +284,336 @@ pub(crate) fn get_app() -> App<'static, 'static> {
                 .takes_value(true),
         )
         .arg(
-            Arg::with_name("parallel")
-                .long("parallel")
+            Arg::with_name("aov")
+                .long("aov")
+                .value_name("AOVS")
+                .required(false)
+                .help("comma separated extra outputs written next to the image, e.g. out_depth.png: depth, normal, albedo, position, material-id, object-id (integer ids, id + 1 in the 24 bits of RGB, red first), material-id-preview, object-id-preview (ids in random colors)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("denoise")
+                .long("denoise")
+                .required(false)
+                .help("denoise the image, guided by the albedo, normal and depth of the first hit"),
+        )
+        .arg(
+            Arg::with_name("alpha")
+                .long("alpha")
+                .required(false)
+                .help("write an alpha channel, transparent where nothing or a holdout is seen, needs a png or tiff output"),
+        )
+        .arg(
+            Arg::with_name("spectral")
+                .long("spectral")
+                .required(false)
+                .help("trace wavelengths instead of RGB, colors are upsampled to spectra"),
+        )
+        .arg(
+            Arg::with_name("light_spectrum")
+                .long("light-spectrum")
+                .value_name("SPECTRUM")
+                .required(false)
+                .help("emission spectrum of the light: a, d50, d65, e (CIE illuminants) or blackbody:KELVIN, white by default")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("light_temperature")
+                .long("light-temperature")
+                .value_name("KELVIN")
+                .required(false)
+                .help("color temperature of the light, short for --light-spectrum blackbody:KELVIN")
+                .takes_value(true)
+                .conflicts_with("light_spectrum"),
+        )
+        .arg(
+            Arg::with_name("light_emission")
+                .long("light-emission")
+                .value_name("AMOUNT")
+                .required(false)
+                .help("light emitted, as a power in watts (e.g. 100W) or lumens (e.g. 1500lm) spread over its surface, or a luminance in cd/m² (default 1000nit, 255 is the white of the image)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("light_texture")
+                .long("light-texture")
+                .value_name("TEXTURE")
+                .required(false)
+                .help("modulates the emission over the light: checker:SQUARES or image:FILE (pixel values used as they are, 255 for full emission), uniform by default")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("light_one_sided")
+                .long("light-one-sided")
+                .required(false)
+                .help("the light only emits outward, its inside stays black"),
+        )
+        .arg(
+            Arg::with_name("lights")
+                .long("light")
+                .value_name("LIGHT")
+                .required(false)
+                .help("adds a punctual light, repeatable: point:X,Y,Z:AMOUNT, spot:X,Y,Z:TX,TY,TZ:INNER,OUTER:AMOUNT (cone aimed at TX,TY,TZ, full angles in degrees, fading out from INNER to OUTER) or directional:DX,DY,DZ:LUXlx (light traveling along DX,DY,DZ), AMOUNT being an intensity in cd (e.g. 100cd) or a power in lm or W, each optionally followed by :SPECTRUM")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("glass")
+                .long("glass")
+                .value_name("IOR")
+                .required(false)
+                .help("index of refraction of the glass spheres: a number (default 1.5), crown, flint, fused-silica, diamond, water, cauchy:A,B, sellmeier:B1,B2,B3,C1,C2,C3 or abbe:ND,VD (wavelengths in µm), dispersion needs --spectral")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("glass_color")
+                .long("glass-color")
+                .value_name("R,G,B[:DISTANCE]")
+                .required(false)
+                .help("color of white light after traveling DISTANCE (default 1) inside the glass spheres, components in (0, 1], clear by default")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("clamp")
+                .long("clamp")
+                .value_name("LUMINANCE")
+                .required(false)
+                .help("scale down samples brighter than LUMINANCE (255 is white), removes fireflies at the cost of some energy")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("debug_samples")
+                .long("debug-samples")
+                .required(false)
+                .help("write out_debug.png next to the image: red pixels got NaN or infinite samples, yellow ones clamped samples"),
+        )
+        .arg(
+            Arg::with_name("bit_depth")
+                .long("bit-depth")
+                .value_name("BITS")
+                .required(false)
+                .help("bits per channel of the written images: 8 (default) or 16, 16 needs a png or tiff output")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("holdout_ground")
+                .long("holdout-ground")
+                .required(false)
+                .help("make the ground a holdout: it masks the spheres but is transparent"),
+        )
+        .arg(
+            Arg::with_name("tile_size")
+                .long("tile-size")
+                .value_name("PIXELS")
+                .required(false)
+                .help("width and height of the tiles rendered by each thread (default 32)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tile_order")
+                .long("tile-order")
+                .value_name("ORDER")
+                .required(false)
+                .help("order in which tiles are rendered: scanline, spiral or hilbert (default)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("crop")
+                .long("crop")
+                .value_name("X0,Y0,X1,Y1")
+                .required(false)
+                .help("only render the window from (X0, Y0) included to (X1, Y1) excluded, in pixels or normalized (e.g. 0.25,0.25,0.75,0.75)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("crop_output")
+                .long("crop-output")
+                .value_name("OUTPUT")
+                .required(false)
+                .help("cropped (default) image or full size canvas, transparent outside of the crop window")
+                .takes_value(true)
+                .requires("crop"),
+        )
+        .arg(
+            Arg::with_name("shard")
+                .long("shard")
+                .value_name("I/N")
+                .required(false)
+                .help("render the I-th of N shares of the samples of every pixel (0 <= I < N) into an accumulation file (-o, out_I.shard by default), see merge")
+                .takes_value(true)
+                .conflicts_with_all(&["adaptive_threshold", "time_limit"]),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .long("threads")
+                .value_name("N")
                 .required(false)
-                .help("enable multi-threading"),
+                .help("number of render threads, 0 for all cores (default 0)")
+                .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name("merge")
+                .about("merges the accumulation files of a sharded render into the final image")
+                .arg(
+                    Arg::with_name("shards")
+                        .value_name("SHARD")
+                        .required(true)
+                        .multiple(true)
+                        .help("accumulation files written with --shard"),
+                )
+                .arg(
+                    Arg::with_name("denoise")
+                        .long("denoise")
+                        .required(false)
+                        .help("denoise the merged image, the shards must be rendered with --denoise"),
+                )
+                .arg(
+                    Arg::with_name("allow_partial")
+                        .long("allow-partial")
+                        .required(false)
+                        .help("merge even if some shards are missing, the image has fewer samples"),
+                )
+                .arg(
+                    Arg::with_name("alpha")
+                        .long("alpha")
+                        .required(false)
+                        .help("write an alpha channel, needs a png or tiff output"),
+                )
+                .arg(
+                    Arg::with_name("bit_depth")
+                        .long("bit-depth")
+                        .value_name("BITS")
+                        .required(false)
+                        .help("bits per channel of the written images: 8 (default) or 16")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .value_name("OUTPUT")
+                        .required(false)
+                        .help("output file path")
+                        .takes_value(true),
+                ),
         )
 }
 
 pub(crate) struct RConfig {
     pub sample_per_pixel: usize,
+    pub sampler: SamplerKind,
+    pub adaptive: Option<Adaptive>,
+    pub progressive: Option<Progressive>,
+    pub time_limit: Option<Duration>,
+    pub seed: u64,
+    pub checkpoint: Option<String>,
+    pub checkpoint_interval: Duration,
+    pub resume: Option<String>,
+    pub filter: Filter,
     pub max_depth: usize,
     pub image_width: usize,
+    pub image_height: Option<u32>,
     pub aspect_ratio: RT,
+    pub look_from: Point3<RT>,
+    pub look_at: Point3<RT>,
+    pub vup: Vector3<RT>,
     pub output_file_path: String,
     pub vfov: RT,
     pub aperture: RT,
-    pub parallel: bool,
+    pub aperture_shape: Aperture,
+    pub focus: Focus,
+    pub camera_path: Option<CameraPath>,
+    pub frames: Option<RangeInclusive<u32>>,
+    pub aovs: Vec<Aov>,
+    pub denoise: bool,
+    pub alpha: bool,
+    pub bit_depth: BitDepth,
+    pub max_sample_luminance: Option<f64>,
+    pub spectral: bool,
+    pub light_spectrum: Option<Spectrum>,
+    pub light_emission: Option<Emission>,
+    pub light_texture: Option<Texture>,
+    pub light_two_sided: bool,
+    /// lights without surface, in addition to the lights of the scene
+    pub lights: Vec<PunctualLight>,
+    pub glass: Ior,
+    pub glass_absorption: RRgb,
+    pub debug_samples: bool,
+    pub holdout_ground: bool,
+    pub crop: Option<Crop>,
+    pub shard: Option<Shard>,
+    pub crop_output: CropOutput,
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    pub threads: usize,
 }
 
 impl Default for RConfig {
     fn default() -> Self {
         RConfig {
             sample_per_pixel: 1,
+            sampler: SamplerKind::Sobol,
+            adaptive: None,
+            progressive: None,
+            time_limit: None,
+            seed: 0,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(300),
+            resume: None,
+            filter: Filter::default(),
             max_depth: 10,
             image_width: 128,
+            image_height: None,
             aspect_ratio: 16.0 / 9.0,
+            look_from: Point3::new(0., 5., 5.),
+            look_at: Point3::new(0., 0., -1.),
+            vup: Vector3::new(0., 1., 0.),
             output_file_path: String::from("out.png"),
             vfov: 90.,
             aperture: 1.0,
-            parallel: false,
+            aperture_shape: Aperture::default(),
+            focus: Focus::LookAt,
+            camera_path: None,
+            frames: None,
+            aovs: vec![],
+            denoise: false,
+            alpha: false,
+            bit_depth: BitDepth::Eight,
+            max_sample_luminance: None,
+            spectral: false,
+            light_spectrum: None,
+            light_emission: None,
+            light_texture: None,
+            light_two_sided: true,
+            lights: vec![],
+            glass: Ior::Constant(1.5),
+            glass_absorption: RRgb::default(),
+            debug_samples: false,
+            holdout_ground: false,
+            crop: None,
+            shard: None,
+            crop_output: CropOutput::Cropped,
+            tile_size: 32,
+            tile_order: TileOrder::Hilbert,
+            threads: 0,
         }
     }
 }
 
 impl RConfig {
     pub(crate) fn get_image_height(&self) -> u32 {
-        (self.image_width as RT / self.aspect_ratio) as u32
+        match self.image_height {
+            Some(image_height) => image_height,
+            None => (self.image_width as RT / self.aspect_ratio) as u32,
+        }
+    }
+
+    /// aspect ratio of the rendered image, follows the image height when it is set
+    pub(crate) fn get_aspect_ratio(&self) -> RT {
+        match self.image_height {
+            Some(image_height) => self.image_width as RT / image_height as RT,
+            None => self.aspect_ratio,
+        }
     }
 
     pub(crate) fn with_sample_per_pixel(self, sample_per_pixel: usize) -> anyhow::Result<Self> {
@@ -104,6 +627,96 @@ impl RConfig {
         }
     }
 
+    pub(crate) fn with_sampler(self, sampler: SamplerKind) -> anyhow::Result<Self> {
+        Ok(RConfig { sampler, ..self })
+    }
+
+    pub(crate) fn with_adaptive(self, adaptive: Adaptive) -> anyhow::Result<Self> {
+        if adaptive.threshold <= 0. {
+            Err(anyhow::anyhow!("adaptive threshold should be > 0"))
+        } else if self.sample_per_pixel < 2 {
+            Err(anyhow::anyhow!(
+                "adaptive sampling needs sample per pixel >= 2 to estimate the variance"
+            ))
+        } else if adaptive.min_spp < 2 {
+            Err(anyhow::anyhow!("min spp should be >= 2"))
+        } else if adaptive.min_spp > self.sample_per_pixel {
+            Err(anyhow::anyhow!("min spp should be <= sample per pixel"))
+        } else if adaptive.max_spp < self.sample_per_pixel {
+            Err(anyhow::anyhow!("max spp should be >= sample per pixel"))
+        } else {
+            Ok(RConfig {
+                adaptive: Some(adaptive),
+                ..self
+            })
+        }
+    }
+
+    pub(crate) fn with_progressive(self, progressive: Progressive) -> anyhow::Result<Self> {
+        if progressive.pass_spp == 0 {
+            Err(anyhow::anyhow!("pass spp should be >= 1"))
+        } else {
+            Ok(RConfig {
+                progressive: Some(progressive),
+                ..self
+            })
+        }
+    }
+
+    pub(crate) fn with_time_limit(self, time_limit: Duration) -> anyhow::Result<Self> {
+        Ok(RConfig {
+            time_limit: Some(time_limit),
+            ..self
+        })
+    }
+
+    pub(crate) fn with_seed(self, seed: u64) -> anyhow::Result<Self> {
+        Ok(RConfig { seed, ..self })
+    }
+
+    pub(crate) fn with_checkpoint(self, checkpoint: String) -> anyhow::Result<Self> {
+        Ok(RConfig {
+            checkpoint: Some(checkpoint),
+            ..self
+        })
+    }
+
+    pub(crate) fn with_checkpoint_interval(
+        self,
+        checkpoint_interval: Duration,
+    ) -> anyhow::Result<Self> {
+        Ok(RConfig {
+            checkpoint_interval,
+            ..self
+        })
+    }
+
+    pub(crate) fn with_resume(self, resume: String) -> anyhow::Result<Self> {
+        Ok(RConfig {
+            resume: Some(resume),
+            ..self
+        })
+    }
+
+    /// most samples a pixel can get
+    pub(crate) fn get_max_sample_per_pixel(&self) -> usize {
+        match &self.adaptive {
+            Some(adaptive) => adaptive.max_spp,
+            None => self.sample_per_pixel,
+        }
+    }
+
+    pub(crate) fn with_filter(self, filter: Filter) -> anyhow::Result<Self> {
+        if filter.radius > 0. && filter.radius <= MAX_FILTER_RADIUS {
+            Ok(RConfig { filter, ..self })
+        } else {
+            Err(anyhow::anyhow!(
+                "filter radius should be > 0 and <= {} pixels",
+                MAX_FILTER_RADIUS
+            ))
+        }
+    }
+
     pub(crate) fn with_max_depth(self, max_depth: usize) -> anyhow::Result<Self> {
         if max_depth != 0 {
             Ok(RConfig { max_depth, ..self })
@@ -123,6 +736,96 @@ impl RConfig {
         }
     }
 
+    /// camera pose given by the command line, used when there is no camera path
+    pub(crate) fn get_camera_pose(&self) -> CameraPose {
+        CameraPose {
+            look_from: self.look_from,
+            look_at: self.look_at,
+            vfov: self.vfov,
+        }
+    }
+
+    /// frames to render, `None` when rendering a single image
+    pub(crate) fn get_frames(&self) -> Option<RangeInclusive<u32>> {
+        match (&self.frames, &self.camera_path) {
+            (Some(frames), _) => Some(frames.clone()),
+            (None, Some(camera_path)) => Some(camera_path.first_frame()..=camera_path.last_frame()),
+            (None, None) => None,
+        }
+    }
+
+    pub(crate) fn with_image_height(self, image_height: u32) -> anyhow::Result<Self> {
+        if image_height != 0 {
+            Ok(RConfig {
+                image_height: Some(image_height),
+                ..self
+            })
+        } else {
+            Err(anyhow::anyhow!("image height should be >= 1"))
+        }
+    }
+
+    pub(crate) fn with_aspect_ratio(self, aspect_ratio: RT) -> anyhow::Result<Self> {
+        if aspect_ratio > 0. {
+            Ok(RConfig {
+                aspect_ratio,
+                ..self
+            })
+        } else {
+            Err(anyhow::anyhow!("aspect ratio should be > 0"))
+        }
+    }
+
+    pub(crate) fn with_look_from(self, look_from: Point3<RT>) -> anyhow::Result<Self> {
+        Ok(RConfig { look_from, ..self })
+    }
+
+    pub(crate) fn with_look_at(self, look_at: Point3<RT>) -> anyhow::Result<Self> {
+        Ok(RConfig { look_at, ..self })
+    }
+
+    pub(crate) fn with_vup(self, vup: Vector3<RT>) -> anyhow::Result<Self> {
+        Ok(RConfig { vup, ..self })
+    }
+
+    /// checks that look from, look at and vup define a valid camera orientation
+    fn check_view(self) -> anyhow::Result<Self> {
+        let view_direction = self.look_at - self.look_from;
+        if view_direction.norm() == 0. {
+            Err(anyhow::anyhow!(
+                "look from and look at should be different points"
+            ))
+        } else if self.vup.cross(&view_direction).norm() == 0. {
+            Err(anyhow::anyhow!(
+                "vup should not be null or parallel to the view direction"
+            ))
+        } else {
+            Ok(self)
+        }
+    }
+
+    /// checks the camera pose of every frame of the camera path
+    fn check_camera_path(self) -> anyhow::Result<Self> {
+        if let Some(camera_path) = &self.camera_path {
+            for frame in camera_path.first_frame()..=camera_path.last_frame() {
+                camera_path
+                    .pose_at(frame)
+                    .check(&self.vup)
+                    .map_err(|e| anyhow::anyhow!("camera path frame {}: {}", frame, e))?;
+            }
+        }
+        Ok(self)
+    }
+
+    /// checks that the image can be written in the format of the output file
+    fn check_output(self) -> anyhow::Result<Self> {
+        if self.shard.is_none() {
+            let canvas = self.crop.is_some() && matches!(self.crop_output, CropOutput::Canvas);
+            check_output_format(&self.output_file_path, self.alpha || canvas, self.bit_depth)?;
+        }
+        Ok(self)
+    }
+
     pub(crate) fn with_output_file_path(self, output_file_path: String) -> anyhow::Result<Self> {
         Ok(RConfig {
             output_file_path,
@@ -149,15 +852,295 @@ impl RConfig {
         }
     }
 
-    pub(crate) fn with_parallel(self, parallel: bool) -> anyhow::Result<Self> {
-        Ok(RConfig { parallel, ..self })
+    pub(crate) fn with_aperture_shape(self, aperture_shape: Aperture) -> anyhow::Result<Self> {
+        Ok(RConfig {
+            aperture_shape,
+            ..self
+        })
     }
 
-    pub(crate) fn from_matches(matches: clap::ArgMatches) -> anyhow::Result<Self> {
-        let config = RConfig::default();
-        let config = if let Some(spp) = matches.value_of("sample_per_pixel") {
-            let spp = spp.parse::<usize>()?;
+    pub(crate) fn with_focus(self, focus: Focus) -> anyhow::Result<Self> {
+        match focus {
+            Focus::Distance(distance) if distance <= 0. => {
+                Err(anyhow::anyhow!("focus distance should be > 0"))
+            }
+            Focus::Auto(Some((x, y)))
+                if x as usize >= self.image_width || y >= self.get_image_height() =>
+            {
+                Err(anyhow::anyhow!(
+                    "autofocus pixel should be inside the image"
+                ))
+            }
+            _ => Ok(RConfig { focus, ..self }),
+        }
+    }
+
+    pub(crate) fn with_camera_path(self, camera_path: CameraPath) -> anyhow::Result<Self> {
+        Ok(RConfig {
+            camera_path: Some(camera_path),
+            ..self
+        })
+    }
+
+    pub(crate) fn with_frames(self, frames: RangeInclusive<u32>) -> anyhow::Result<Self> {
+        if frames.start() <= frames.end() {
+            Ok(RConfig {
+                frames: Some(frames),
+                ..self
+            })
+        } else {
+            Err(anyhow::anyhow!("frames start should be <= frames end"))
+        }
+    }
+
+    pub(crate) fn with_aovs(self, aovs: Vec<Aov>) -> anyhow::Result<Self> {
+        let mut unique_aovs = Vec::with_capacity(aovs.len());
+        for aov in aovs {
+            if !unique_aovs.contains(&aov) {
+                unique_aovs.push(aov);
+            }
+        }
+        Ok(RConfig {
+            aovs: unique_aovs,
+            ..self
+        })
+    }
+
+    pub(crate) fn with_denoise(self, denoise: bool) -> anyhow::Result<Self> {
+        Ok(RConfig { denoise, ..self })
+    }
+
+    pub(crate) fn with_alpha(self, alpha: bool) -> anyhow::Result<Self> {
+        Ok(RConfig { alpha, ..self })
+    }
+
+    pub(crate) fn with_bit_depth(self, bit_depth: BitDepth) -> anyhow::Result<Self> {
+        Ok(RConfig { bit_depth, ..self })
+    }
+
+    pub(crate) fn with_max_sample_luminance(self, luminance: f64) -> anyhow::Result<Self> {
+        if luminance > 0. {
+            Ok(RConfig {
+                max_sample_luminance: Some(luminance),
+                ..self
+            })
+        } else {
+            Err(anyhow::anyhow!("clamp luminance should be > 0"))
+        }
+    }
+
+    pub(crate) fn with_spectral(self, spectral: bool) -> anyhow::Result<Self> {
+        Ok(RConfig { spectral, ..self })
+    }
+
+    pub(crate) fn with_light_spectrum(self, light_spectrum: Spectrum) -> anyhow::Result<Self> {
+        Ok(RConfig {
+            light_spectrum: Some(light_spectrum),
+            ..self
+        })
+    }
+
+    pub(crate) fn with_light_emission(self, light_emission: Emission) -> anyhow::Result<Self> {
+        Ok(RConfig {
+            light_emission: Some(light_emission),
+            ..self
+        })
+    }
+
+    pub(crate) fn with_light_texture(self, light_texture: Texture) -> anyhow::Result<Self> {
+        Ok(RConfig {
+            light_texture: Some(light_texture),
+            ..self
+        })
+    }
+
+    pub(crate) fn with_light_two_sided(self, light_two_sided: bool) -> anyhow::Result<Self> {
+        Ok(RConfig {
+            light_two_sided,
+            ..self
+        })
+    }
+
+    pub(crate) fn with_lights(self, lights: Vec<PunctualLight>) -> anyhow::Result<Self> {
+        Ok(RConfig { lights, ..self })
+    }
+
+    pub(crate) fn with_glass(self, glass: Ior) -> anyhow::Result<Self> {
+        Ok(RConfig { glass, ..self })
+    }
+
+    /// sets the absorption of the glass from the color transmitted after `distance`
+    pub(crate) fn with_glass_color(self, color: [RT; 3], distance: RT) -> anyhow::Result<Self> {
+        if color.iter().any(|&c| c <= 0. || c > 1.) {
+            Err(anyhow::anyhow!(
+                "glass color components should be in (0, 1]"
+            ))
+        } else if distance <= 0. {
+            Err(anyhow::anyhow!("glass color distance should be > 0"))
+        } else {
+            // Beer-Lambert law: color = exp(-absorption * distance)
+            let [r, g, b] = color.map(|c| -(c as f64).ln() / distance as f64);
+            Ok(RConfig {
+                glass_absorption: RRgb::new(r, g, b),
+                ..self
+            })
+        }
+    }
+
+    pub(crate) fn with_debug_samples(self, debug_samples: bool) -> anyhow::Result<Self> {
+        Ok(RConfig {
+            debug_samples,
+            ..self
+        })
+    }
+
+    pub(crate) fn with_holdout_ground(self, holdout_ground: bool) -> anyhow::Result<Self> {
+        Ok(RConfig {
+            holdout_ground,
+            ..self
+        })
+    }
+
+    pub(crate) fn with_crop(self, crop: Crop) -> anyhow::Result<Self> {
+        if crop.width == 0 || crop.height == 0 {
+            Err(anyhow::anyhow!("crop window should not be empty"))
+        } else if (crop.x + crop.width) as usize > self.image_width
+            || crop.y + crop.height > self.get_image_height()
+        {
+            Err(anyhow::anyhow!("crop window should be inside the image"))
+        } else {
+            Ok(RConfig {
+                crop: Some(crop),
+                ..self
+            })
+        }
+    }
+
+    pub(crate) fn with_crop_output(self, crop_output: CropOutput) -> anyhow::Result<Self> {
+        Ok(RConfig {
+            crop_output,
+            ..self
+        })
+    }
+
+    pub(crate) fn with_shard(self, shard: Shard) -> anyhow::Result<Self> {
+        if shard.index >= shard.count {
+            Err(anyhow::anyhow!("shard index should be < shard count"))
+        } else if shard.count > self.sample_per_pixel {
+            Err(anyhow::anyhow!(
+                "shard count should be <= samples per pixel, every shard renders at least one sample"
+            ))
+        } else if ImageFormat::from_path(&self.output_file_path).is_ok() {
+            Err(anyhow::anyhow!(
+                "a shard is written to an accumulation file, not to the image {}, use e.g. -o out_{}.shard",
+                self.output_file_path,
+                shard.index
+            ))
+        } else {
+            Ok(RConfig {
+                shard: Some(shard),
+                ..self
+            })
+        }
+    }
+
+    pub(crate) fn with_tile_size(self, tile_size: u32) -> anyhow::Result<Self> {
+        if tile_size == 0 {
+            return Err(anyhow::anyhow!("tile size should be > 0"));
+        }
+        Ok(RConfig { tile_size, ..self })
+    }
+
+    pub(crate) fn with_tile_order(self, tile_order: TileOrder) -> anyhow::Result<Self> {
+        Ok(RConfig { tile_order, ..self })
+    }
+
+    pub(crate) fn with_threads(self, threads: usize) -> anyhow::Result<Self> {
+        Ok(RConfig { threads, ..self })
+    }
+
+    pub(crate) fn from_matches(matches: clap::ArgMatches) -> anyhow::Result<Self> {
+        let config = RConfig::default();
+        let config = if let Some(spp) = matches.value_of("sample_per_pixel") {
+            let spp = spp.parse::<usize>()?;
             config.with_sample_per_pixel(spp)?
+        } else if matches.is_present("time_limit") {
+            config.with_sample_per_pixel(TIME_LIMITED_MAX_SAMPLE_PER_PIXEL)?
+        } else {
+            config
+        };
+        let config = if let Some(sampler) = matches.value_of("sampler") {
+            let sampler = sampler.parse::<SamplerKind>()?;
+            config.with_sampler(sampler)?
+        } else {
+            config
+        };
+        let config = if let Some(threshold) = matches.value_of("adaptive_threshold") {
+            let threshold = threshold.parse::<f64>()?;
+            let min_spp = match matches.value_of("min_spp") {
+                Some(min_spp) => min_spp.parse::<usize>()?,
+                None => config.sample_per_pixel.min(16),
+            };
+            let max_spp = match matches.value_of("max_spp") {
+                Some(max_spp) => max_spp.parse::<usize>()?,
+                None => 4 * config.sample_per_pixel,
+            };
+            config.with_adaptive(Adaptive {
+                threshold,
+                min_spp,
+                max_spp,
+            })?
+        } else {
+            config
+        };
+        let config = if let Some(pass_spp) = matches.value_of("pass_spp") {
+            let pass_spp = pass_spp.parse::<usize>()?;
+            let snapshot_interval = match matches.value_of("snapshot_interval") {
+                Some(seconds) => Some(parse_duration(seconds)?),
+                None => None,
+            };
+            config.with_progressive(Progressive {
+                pass_spp,
+                snapshot_interval,
+            })?
+        } else {
+            config
+        };
+        let config = if let Some(time_limit) = matches.value_of("time_limit") {
+            config.with_time_limit(parse_duration(time_limit)?)?
+        } else {
+            config
+        };
+        let config = if let Some(seed) = matches.value_of("seed") {
+            config.with_seed(seed.parse::<u64>()?)?
+        } else {
+            config
+        };
+        let config = if let Some(checkpoint) = matches.value_of("checkpoint") {
+            config.with_checkpoint(String::from(checkpoint))?
+        } else {
+            config
+        };
+        let config = if let Some(checkpoint_interval) = matches.value_of("checkpoint_interval") {
+            config.with_checkpoint_interval(parse_duration(checkpoint_interval)?)?
+        } else {
+            config
+        };
+        let config = if let Some(resume) = matches.value_of("resume") {
+            config.with_resume(String::from(resume))?
+        } else {
+            config
+        };
+        let config = if matches.is_present("filter") || matches.is_present("filter_radius") {
+            let kind = match matches.value_of("filter") {
+                Some(kind) => kind.parse::<FilterKind>()?,
+                None => config.filter.kind,
+            };
+            let radius = match matches.value_of("filter_radius") {
+                Some(radius) => radius.parse::<RT>()?,
+                None => kind.default_radius(),
+            };
+            config.with_filter(Filter { kind, radius })?
         } else {
             config
         };
@@ -173,6 +1156,37 @@ impl RConfig {
         } else {
             config
         };
+        let config = if let Some(image_height) = matches.value_of("image_height") {
+            let image_height = image_height.parse::<u32>()?;
+            config.with_image_height(image_height)?
+        } else {
+            config
+        };
+        let config = if let Some(aspect_ratio) = matches.value_of("aspect_ratio") {
+            let aspect_ratio = aspect_ratio.parse::<RT>()?;
+            config.with_aspect_ratio(aspect_ratio)?
+        } else {
+            config
+        };
+        let config = if let Some(look_from) = matches.value_of("look_from") {
+            let [x, y, z] = parse_triplet(look_from)?;
+            config.with_look_from(Point3::new(x, y, z))?
+        } else {
+            config
+        };
+        let config = if let Some(look_at) = matches.value_of("look_at") {
+            let [x, y, z] = parse_triplet(look_at)?;
+            config.with_look_at(Point3::new(x, y, z))?
+        } else {
+            config
+        };
+        let config = if let Some(vup) = matches.value_of("vup") {
+            let [x, y, z] = parse_triplet(vup)?;
+            config.with_vup(Vector3::new(x, y, z))?
+        } else {
+            config
+        };
+        let config = config.check_view()?;
         let config = if let Some(output_file_path) = matches.value_of("output") {
             config.with_output_file_path(String::from(output_file_path))?
         } else {
@@ -190,11 +1204,360 @@ impl RConfig {
         } else {
             config
         };
-        let config = if matches.is_present("parallel") {
-            config.with_parallel(true)?
+        let config = if let Some(aperture_shape) = matches.value_of("bokeh") {
+            let aperture_shape = aperture_shape.parse::<Aperture>()?;
+            config.with_aperture_shape(aperture_shape)?
+        } else {
+            config
+        };
+        let config = if let Some(focus_dist) = matches.value_of("focus_dist") {
+            let focus_dist = focus_dist.parse::<RT>()?;
+            config.with_focus(Focus::Distance(focus_dist))?
+        } else {
+            config
+        };
+        let config = if matches.is_present("autofocus") {
+            let pixel = match matches.value_of("autofocus") {
+                Some(pixel) => Some(parse_pixel(pixel)?),
+                None => None,
+            };
+            config.with_focus(Focus::Auto(pixel))?
+        } else {
+            config
+        };
+        let config = if let Some(camera_path) = matches.value_of("camera_path") {
+            let interpolation = match matches.value_of("interpolation") {
+                Some(interpolation) => interpolation.parse::<Interpolation>()?,
+                None => Interpolation::CatmullRom,
+            };
+            config.with_camera_path(CameraPath::open(camera_path, interpolation)?)?
+        } else {
+            config
+        };
+        let config = if let Some(frames) = matches.value_of("frames") {
+            config.with_frames(parse_frames(frames)?)?
+        } else {
+            config
+        };
+        let config = if let Some(aovs) = matches.value_of("aov") {
+            let aovs = aovs
+                .split(',')
+                .map(|aov| aov.trim().parse::<Aov>())
+                .collect::<anyhow::Result<Vec<Aov>>>()?;
+            config.with_aovs(aovs)?
+        } else {
+            config
+        };
+        let config = if matches.is_present("denoise") {
+            config.with_denoise(true)?
+        } else {
+            config
+        };
+        let config = if matches.is_present("alpha") {
+            config.with_alpha(true)?
+        } else {
+            config
+        };
+        let config = if matches.is_present("spectral") {
+            config.with_spectral(true)?
+        } else {
+            config
+        };
+        let config = if let Some(light_spectrum) = matches.value_of("light_spectrum") {
+            config.with_light_spectrum(light_spectrum.parse::<Spectrum>()?)?
+        } else {
+            config
+        };
+        let config = if let Some(temperature) = matches.value_of("light_temperature") {
+            config.with_light_spectrum(format!("blackbody:{}", temperature).parse::<Spectrum>()?)?
+        } else {
+            config
+        };
+        let config = if let Some(light_emission) = matches.value_of("light_emission") {
+            config.with_light_emission(light_emission.parse::<Emission>()?)?
+        } else {
+            config
+        };
+        let config = if let Some(light_texture) = matches.value_of("light_texture") {
+            config.with_light_texture(light_texture.parse::<Texture>()?)?
+        } else {
+            config
+        };
+        let config = if matches.is_present("light_one_sided") {
+            config.with_light_two_sided(false)?
+        } else {
+            config
+        };
+        let config = if let Some(lights) = matches.values_of("lights") {
+            config.with_lights(
+                lights
+                    .map(|light| light.parse::<PunctualLight>())
+                    .collect::<anyhow::Result<Vec<PunctualLight>>>()?,
+            )?
+        } else {
+            config
+        };
+        let config = if let Some(glass) = matches.value_of("glass") {
+            config.with_glass(glass.parse::<Ior>()?)?
+        } else {
+            config
+        };
+        let config = if let Some(glass_color) = matches.value_of("glass_color") {
+            let (color, distance) = match glass_color.split_once(':') {
+                Some((color, distance)) => (color, distance.trim().parse::<RT>()?),
+                None => (glass_color, 1.),
+            };
+            config.with_glass_color(parse_triplet(color)?, distance)?
+        } else {
+            config
+        };
+        let config = if let Some(luminance) = matches.value_of("clamp") {
+            config.with_max_sample_luminance(luminance.parse::<f64>()?)?
         } else {
             config
         };
-        Ok(config)
+        let config = if matches.is_present("debug_samples") {
+            config.with_debug_samples(true)?
+        } else {
+            config
+        };
+        let config = if let Some(bit_depth) = matches.value_of("bit_depth") {
+            config.with_bit_depth(bit_depth.parse::<BitDepth>()?)?
+        } else {
+            config
+        };
+        let config = if matches.is_present("holdout_ground") {
+            config.with_holdout_ground(true)?
+        } else {
+            config
+        };
+        // after the image size, normalized coordinates depend on it
+        let config = if let Some(crop) = matches.value_of("crop") {
+            let crop = parse_crop(crop, config.image_width as u32, config.get_image_height())?;
+            config.with_crop(crop)?
+        } else {
+            config
+        };
+        let config = if let Some(crop_output) = matches.value_of("crop_output") {
+            config.with_crop_output(crop_output.parse::<CropOutput>()?)?
+        } else {
+            config
+        };
+        let config = if let Some(shard) = matches.value_of("shard") {
+            let shard = parse_shard(shard)?;
+            let config = if matches.is_present("output") {
+                config
+            } else {
+                config.with_output_file_path(format!("out_{}.shard", shard.index))?
+            };
+            config.with_shard(shard)?
+        } else {
+            config
+        };
+        let config = if let Some(tile_size) = matches.value_of("tile_size") {
+            config.with_tile_size(tile_size.parse::<u32>()?)?
+        } else {
+            config
+        };
+        let config = if let Some(tile_order) = matches.value_of("tile_order") {
+            config.with_tile_order(tile_order.parse::<TileOrder>()?)?
+        } else {
+            config
+        };
+        let config = if let Some(threads) = matches.value_of("threads") {
+            config.with_threads(threads.parse::<usize>()?)?
+        } else {
+            config
+        };
+        config.check_camera_path()?.check_output()
+    }
+}
+
+fn parse_pixel(pixel: &str) -> anyhow::Result<(u32, u32)> {
+    let coordinates: Vec<&str> = pixel.split(',').collect();
+    match coordinates.as_slice() {
+        [x, y] => Ok((x.trim().parse::<u32>()?, y.trim().parse::<u32>()?)),
+        _ => Err(anyhow::anyhow!("invalid pixel {}, expected X,Y", pixel)),
+    }
+}
+
+fn parse_shard(shard: &str) -> anyhow::Result<Shard> {
+    let parts: Vec<&str> = shard.split('/').collect();
+    match parts.as_slice() {
+        [index, count] => Ok(Shard {
+            index: index.trim().parse::<usize>()?,
+            count: count.trim().parse::<usize>()?,
+        }),
+        _ => Err(anyhow::anyhow!("invalid shard {}, expected I/N", shard)),
+    }
+}
+
+/// parses `X0,Y0,X1,Y1` pixel coordinates, or normalized coordinates if any has a decimal point
+fn parse_crop(crop: &str, image_width: u32, image_height: u32) -> anyhow::Result<Crop> {
+    let coordinates: Vec<&str> = crop.split(',').map(|c| c.trim()).collect();
+    let [x0, y0, x1, y1] = match coordinates.as_slice() {
+        [x0, y0, x1, y1] if coordinates.iter().any(|c| c.contains('.')) => {
+            let to_pixels = |c: &str, size: u32| -> anyhow::Result<u32> {
+                let c = c.parse::<f64>()?;
+                if (0. ..=1.).contains(&c) {
+                    Ok((c * size as f64).round() as u32)
+                } else {
+                    Err(anyhow::anyhow!(
+                        "normalized crop coordinates should be in [0, 1]"
+                    ))
+                }
+            };
+            [
+                to_pixels(x0, image_width)?,
+                to_pixels(y0, image_height)?,
+                to_pixels(x1, image_width)?,
+                to_pixels(y1, image_height)?,
+            ]
+        }
+        [x0, y0, x1, y1] => [
+            x0.parse::<u32>()?,
+            y0.parse::<u32>()?,
+            x1.parse::<u32>()?,
+            y1.parse::<u32>()?,
+        ],
+        _ => {
+            return Err(anyhow::anyhow!(
+                "invalid crop {}, expected X0,Y0,X1,Y1",
+                crop
+            ))
+        }
+    };
+    Ok(Crop {
+        x: x0,
+        y: y0,
+        width: x1.saturating_sub(x0),
+        height: y1.saturating_sub(y0),
+    })
+}
+
+/// parses durations such as `90`, `90s`, `1.5m`, `1h30m`, plain numbers are seconds
+fn parse_duration(duration: &str) -> anyhow::Result<Duration> {
+    let invalid = || {
+        anyhow::anyhow!(
+            "invalid duration {}, expected e.g. 90s, 5m or 1h30m",
+            duration
+        )
+    };
+    if let Ok(seconds) = duration.parse::<f64>() {
+        return if seconds >= 0. && seconds.is_finite() {
+            Ok(Duration::from_secs_f64(seconds))
+        } else {
+            Err(invalid())
+        };
+    }
+    let mut seconds = 0f64;
+    let mut number = String::new();
+    for c in duration.chars() {
+        let unit = match c {
+            'h' => 3600.,
+            'm' => 60.,
+            's' => 1.,
+            _ => {
+                number.push(c);
+                continue;
+            }
+        };
+        let value = number.parse::<f64>().map_err(|_| invalid())?;
+        if value < 0. || !value.is_finite() {
+            return Err(invalid());
+        }
+        seconds += value * unit;
+        number.clear();
+    }
+    if number.is_empty() {
+        Ok(Duration::from_secs_f64(seconds))
+    } else {
+        Err(invalid())
+    }
+}
+
+fn parse_frames(frames: &str) -> anyhow::Result<RangeInclusive<u32>> {
+    let bounds: Vec<&str> = frames.split("..").collect();
+    match bounds.as_slice() {
+        [start, end] => Ok(start.trim().parse::<u32>()?..=end.trim().parse::<u32>()?),
+        _ => Err(anyhow::anyhow!(
+            "invalid frames {}, expected START..END",
+            frames
+        )),
+    }
+}
+
+pub(crate) fn parse_triplet(triplet: &str) -> anyhow::Result<[RT; 3]> {
+    let coordinates: Vec<&str> = triplet.split(',').collect();
+    match coordinates.as_slice() {
+        [x, y, z] => Ok([
+            x.trim().parse::<RT>()?,
+            y.trim().parse::<RT>()?,
+            z.trim().parse::<RT>()?,
+        ]),
+        _ => Err(anyhow::anyhow!(
+            "invalid triplet {}, expected X,Y,Z",
+            triplet
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_from_args(args: &[&str]) -> anyhow::Result<RConfig> {
+        let matches = get_app().get_matches_from_safe([&["rray"], args].concat())?;
+        RConfig::from_matches(matches)
+    }
+
+    #[test]
+    fn adaptive_needs_two_samples_per_pixel() {
+        let error = config_from_args(&["-s", "1", "--adaptive-threshold", "0.01"])
+            .err()
+            .expect("adaptive sampling with 1 spp should be rejected");
+        assert!(error.to_string().contains("sample per pixel >= 2"));
+        assert!(config_from_args(&["-s", "2", "--adaptive-threshold", "0.01"]).is_ok());
+    }
+
+    #[test]
+    fn alpha_needs_an_alpha_format() {
+        assert!(config_from_args(&["--alpha", "-o", "out.jpg"]).is_err());
+        assert!(config_from_args(&[
+            "--crop",
+            "0,0,8,8",
+            "--crop-output",
+            "canvas",
+            "-o",
+            "out.jpg"
+        ])
+        .is_err());
+        assert!(config_from_args(&["--alpha", "-o", "out.png"]).is_ok());
+    }
+
+    #[test]
+    fn sixteen_bits_need_png_or_tiff() {
+        assert!(config_from_args(&["--bit-depth", "16", "-o", "out.jpg"]).is_err());
+        assert!(config_from_args(&["--bit-depth", "16", "-o", "out.tiff"]).is_ok());
+    }
+
+    #[test]
+    fn camera_path_overshoot_is_rejected() {
+        // the spline overshoots 180 degrees between frames 0 and 10
+        let path = std::env::temp_dir()
+            .join(format!("rray_test_{}_overshoot.path", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        std::fs::write(
+            &path,
+            "0 0,0,5 0,0,0 170\n10 0,0,5 0,0,0 170\n11 0,0,5 0,0,0 10\n",
+        )
+        .unwrap();
+        let error = config_from_args(&["--camera-path", &path])
+            .err()
+            .expect("vfov overshooting 180 degrees should be rejected");
+        assert!(error.to_string().starts_with("camera path frame"));
+        assert!(config_from_args(&["--camera-path", &path, "--interpolation", "linear"]).is_ok());
+        std::fs::remove_file(&path).unwrap();
     }
 }
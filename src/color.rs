@@ -4,7 +4,7 @@ use std::ops;
 
 pub(crate) type CT = u8;
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Default)]
 pub(crate) struct RRgb {
     r: f64,
     g: f64,
@@ -64,6 +64,20 @@ impl RRgb {
     pub(crate) fn new(r: f64, g: f64, b: f64) -> Self {
         RRgb { r, g, b }
     }
+
+    pub(crate) fn to_array(&self) -> [f64; 3] {
+        [self.r, self.g, self.b]
+    }
+
+    /// relative luminance (Rec. 709)
+    pub(crate) fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    /// false if any channel is NaN or infinite
+    pub(crate) fn is_finite(&self) -> bool {
+        self.r.is_finite() && self.g.is_finite() && self.b.is_finite()
+    }
 }
 
 impl From<RRgb> for Rgb<CT> {
@@ -86,3 +100,11 @@ impl From<RRgb> for Rgb<CT> {
         Rgb([r, g, b])
     }
 }
+
+/// 16 bits per channel, the 8 bits range [0, 255] maps to [0, 65535]
+impl From<RRgb> for Rgb<u16> {
+    fn from(rrgb: RRgb) -> Self {
+        let to_u16 = |c: f64| (c * 257.).clamp(0., u16::MAX as f64).round() as u16;
+        Rgb([to_u16(rrgb.r), to_u16(rrgb.g), to_u16(rrgb.b)])
+    }
+}
@@ -0,0 +1,128 @@
+use crate::aov::Aov;
+use crate::color::RRgb;
+use crate::film::Film;
+use rayon::prelude::*;
+
+/// half width of the filter window, in pixels
+const RADIUS: i64 = 6;
+const SIGMA_SPATIAL: f64 = 3.;
+/// albedo difference, per channel
+const SIGMA_ALBEDO: f64 = 0.1;
+/// one minus the cosine between normals
+const SIGMA_NORMAL: f64 = 0.1;
+/// depth difference relative to the depth of the filtered pixel
+const SIGMA_DEPTH: f64 = 0.05;
+/// luminance difference, in standard deviations of the pixels means
+const SIGMA_COLOR: f64 = 3.;
+
+/// Guide of a pixel, from the aovs of the first hit.
+struct Feature {
+    albedo: [f64; 3],
+    normal: [f64; 3],
+    depth: f64,
+}
+
+/// denoises the film with a joint cross-bilateral filter, returns the color of each pixel
+///
+/// Neighbours are weighted by their distance and by how close their albedo, normal and depth
+/// are, so that edges and textures are kept. The lighting is filtered separately from the
+/// albedo, and the luminance difference is measured against the pixels variance: converged
+/// pixels are barely touched. Pixels where nothing was hit are left as is.
+pub(crate) fn denoise(film: &Film) -> Vec<RRgb> {
+    let colors: Vec<[f64; 3]> = film.pixels().iter().map(|p| p.mean().to_array()).collect();
+    let features: Vec<Option<Feature>> = film
+        .aovs()
+        .iter()
+        .map(|aov| {
+            Some(Feature {
+                albedo: aov.value(Aov::Albedo)?,
+                normal: aov.value(Aov::Normal)?,
+                depth: aov.value(Aov::Depth)?[0],
+            })
+        })
+        .collect();
+    if features.len() != colors.len() {
+        // no aov captured, nothing to guide the filter
+        return colors.iter().map(|&[r, g, b]| RRgb::new(r, g, b)).collect();
+    }
+    // lighting without the surface color, with the variance of its luminance
+    let demodulate = |value: f64, albedo: f64| value / albedo.max(0.01);
+    let irradiances: Vec<[f64; 3]> = colors
+        .iter()
+        .zip(features.iter())
+        .map(|(color, feature)| match feature {
+            Some(feature) => [0, 1, 2].map(|i| demodulate(color[i], feature.albedo[i])),
+            None => *color,
+        })
+        .collect();
+    let variances: Vec<f64> = film
+        .pixels()
+        .iter()
+        .zip(features.iter())
+        .map(|(pixel, feature)| {
+            let albedo_luminance = match feature {
+                Some(feature) => luminance(&feature.albedo).max(0.01),
+                None => 1.,
+            };
+            pixel.mean_luminance_variance() / (albedo_luminance * albedo_luminance)
+        })
+        .collect();
+
+    let (width, height) = (film.width() as i64, film.height() as i64);
+    (0..colors.len())
+        .into_par_iter()
+        .map(|p| {
+            let feature = match &features[p] {
+                Some(feature) => feature,
+                None => {
+                    let [r, g, b] = colors[p];
+                    return RRgb::new(r, g, b);
+                }
+            };
+            let (x, y) = (p as i64 % width, p as i64 / width);
+            let luminance_p = luminance(&irradiances[p]);
+            let mut sum = [0f64; 3];
+            let mut weight_sum = 0f64;
+            for qy in (y - RADIUS).max(0)..=(y + RADIUS).min(height - 1) {
+                for qx in (x - RADIUS).max(0)..=(x + RADIUS).min(width - 1) {
+                    let q = (qy * width + qx) as usize;
+                    let other = match &features[q] {
+                        Some(other) => other,
+                        None => continue,
+                    };
+                    let spatial = ((qx - x).pow(2) + (qy - y).pow(2)) as f64
+                        / (2. * SIGMA_SPATIAL * SIGMA_SPATIAL);
+                    let albedo = (0..3)
+                        .map(|i| (feature.albedo[i] - other.albedo[i]).powi(2))
+                        .sum::<f64>()
+                        / (2. * SIGMA_ALBEDO * SIGMA_ALBEDO);
+                    let cosine = (0..3)
+                        .map(|i| feature.normal[i] * other.normal[i])
+                        .sum::<f64>();
+                    let normal = (1. - cosine).max(0.) / SIGMA_NORMAL;
+                    let depth_scale = (SIGMA_DEPTH * feature.depth.abs()).max(1e-6);
+                    let depth = ((feature.depth - other.depth) / depth_scale).powi(2) / 2.;
+                    let variance = variances[p] + variances[q];
+                    let color = if variance.is_finite() {
+                        (luminance_p - luminance(&irradiances[q])).powi(2)
+                            / (2. * SIGMA_COLOR * SIGMA_COLOR * variance + 1e-6)
+                    } else {
+                        0.
+                    };
+                    let weight = (-(spatial + albedo + normal + depth + color)).exp();
+                    for i in 0..3 {
+                        sum[i] += irradiances[q][i] * weight;
+                    }
+                    weight_sum += weight;
+                }
+            }
+            // the pixel itself always has a weight of 1
+            let [r, g, b] = [0, 1, 2].map(|i| sum[i] / weight_sum * feature.albedo[i].max(0.01));
+            RRgb::new(r, g, b)
+        })
+        .collect()
+}
+
+fn luminance(&[r, g, b]: &[f64; 3]) -> f64 {
+    RRgb::new(r, g, b).luminance()
+}
@@ -0,0 +1,520 @@
+use crate::aov::{read_aov_kinds, write_aov_kinds, Aov, AovPixel, AovSample};
+use crate::color::RRgb;
+use crate::filter::Filter;
+use crate::output::BitDepth;
+use crate::ray::RT;
+use crate::tile::Tile;
+use image::{DynamicImage, ImageBuffer, Rgb, RgbImage, Rgba};
+use std::io::{Read, Write};
+
+/// Accumulated samples of a pixel.
+///
+/// The color is the filter weighted sum of the samples splatted to the pixel, the
+/// luminance statistics and the sample count only cover the samples taken within the pixel.
+/// NaN or infinite samples are counted but not accumulated.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Pixel {
+    color_sum: RRgb,
+    /// filter weighted sum of the samples coverage, 0 for a miss or a holdout
+    alpha_sum: f64,
+    weight_sum: f64,
+    luminance_sum: f64,
+    luminance_squared_sum: f64,
+    pub sample_count: usize,
+    /// NaN or infinite samples, discarded
+    invalid_sample_count: usize,
+    /// samples brighter than the maximum sample luminance
+    clamped_sample_count: usize,
+}
+
+impl Pixel {
+    /// records a sample taken within the pixel
+    fn add_sample(&mut self, color: &RRgb, clamped: bool) {
+        let luminance = color.luminance();
+        self.luminance_sum += luminance;
+        self.luminance_squared_sum += luminance * luminance;
+        self.sample_count += 1;
+        self.clamped_sample_count += clamped as usize;
+    }
+
+    /// records a NaN or infinite sample taken within the pixel, it still uses a sample index
+    fn add_invalid_sample(&mut self) {
+        self.sample_count += 1;
+        self.invalid_sample_count += 1;
+    }
+
+    /// adds the contribution of a sample to the pixel color
+    fn add_splat(&mut self, color: &RRgb, alpha: f64, weight: RT) {
+        self.color_sum = self.color_sum.clone() + color.clone() * weight;
+        self.alpha_sum += alpha * weight as f64;
+        self.weight_sum += weight as f64;
+    }
+
+    pub(crate) fn merge(&mut self, other: &Pixel) {
+        self.color_sum = self.color_sum.clone() + other.color_sum.clone();
+        self.alpha_sum += other.alpha_sum;
+        self.weight_sum += other.weight_sum;
+        self.luminance_sum += other.luminance_sum;
+        self.luminance_squared_sum += other.luminance_squared_sum;
+        self.sample_count += other.sample_count;
+        self.invalid_sample_count += other.invalid_sample_count;
+        self.clamped_sample_count += other.clamped_sample_count;
+    }
+
+    pub(crate) fn mean(&self) -> RRgb {
+        if self.weight_sum == 0. {
+            RRgb::default()
+        } else {
+            self.color_sum.clone() * (1. / self.weight_sum) as RT
+        }
+    }
+
+    /// fraction of the pixel covered by visible surfaces
+    pub(crate) fn alpha(&self) -> f64 {
+        if self.weight_sum == 0. {
+            0.
+        } else {
+            self.alpha_sum / self.weight_sum
+        }
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        for value in self.color_sum.to_array().iter().chain(&[
+            self.alpha_sum,
+            self.weight_sum,
+            self.luminance_sum,
+            self.luminance_squared_sum,
+        ]) {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for count in &[
+            self.sample_count,
+            self.invalid_sample_count,
+            self.clamped_sample_count,
+        ] {
+            writer.write_all(&(*count as u64).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut values = [0f64; 7];
+        for value in values.iter_mut() {
+            *value = f64::from_le_bytes(read_bytes(reader)?);
+        }
+        let [r, g, b, alpha_sum, weight_sum, luminance_sum, luminance_squared_sum] = values;
+        let mut counts = [0usize; 3];
+        for count in counts.iter_mut() {
+            *count = u64::from_le_bytes(read_bytes(reader)?) as usize;
+        }
+        let [sample_count, invalid_sample_count, clamped_sample_count] = counts;
+        Ok(Pixel {
+            color_sum: RRgb::new(r, g, b),
+            alpha_sum,
+            weight_sum,
+            luminance_sum,
+            luminance_squared_sum,
+            sample_count,
+            invalid_sample_count,
+            clamped_sample_count,
+        })
+    }
+
+    /// samples accumulated in the luminance statistics
+    fn valid_sample_count(&self) -> usize {
+        self.sample_count - self.invalid_sample_count
+    }
+
+    /// variance of the mean luminance, infinite below 2 samples
+    pub(crate) fn mean_luminance_variance(&self) -> f64 {
+        if self.valid_sample_count() < 2 {
+            return f64::INFINITY;
+        }
+        let n = self.valid_sample_count() as f64;
+        let mean = self.luminance_sum / n;
+        let variance = ((self.luminance_squared_sum / n - mean * mean) * n / (n - 1.)).max(0.);
+        variance / n
+    }
+
+    /// standard error of the mean luminance relative to the mean luminance
+    ///
+    /// Dark pixels are compared to a luminance of 1, so that their noise is not overestimated.
+    pub(crate) fn relative_error(&self) -> f64 {
+        if self.valid_sample_count() < 2 {
+            return f64::INFINITY;
+        }
+        let mean = self.luminance_sum / self.valid_sample_count() as f64;
+        self.mean_luminance_variance().sqrt() / mean.max(1.)
+    }
+}
+
+/// Image being rendered, y axis points upward.
+pub(crate) struct Film {
+    width: u32,
+    height: u32,
+    pixels: Vec<Pixel>,
+    /// outputs written besides the color
+    aov_kinds: Vec<Aov>,
+    /// one per pixel, empty if aovs are neither written nor used for denoising
+    aovs: Vec<AovPixel>,
+}
+
+impl Film {
+    pub(crate) fn new(width: u32, height: u32, aov_kinds: Vec<Aov>, capture_aovs: bool) -> Self {
+        let pixel_count = (width * height) as usize;
+        let aov_count = if capture_aovs || !aov_kinds.is_empty() {
+            pixel_count
+        } else {
+            0
+        };
+        Film {
+            width,
+            height,
+            pixels: vec![Pixel::default(); pixel_count],
+            aov_kinds,
+            aovs: vec![AovPixel::default(); aov_count],
+        }
+    }
+
+    pub(crate) fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub(crate) fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub(crate) fn pixels(&self) -> &[Pixel] {
+        &self.pixels
+    }
+
+    pub(crate) fn aov_kinds(&self) -> &[Aov] {
+        &self.aov_kinds
+    }
+
+    pub(crate) fn aovs(&self) -> &[AovPixel] {
+        &self.aovs
+    }
+
+    /// adds the pixels of a tile rendered separately
+    pub(crate) fn merge_tile(&mut self, film_tile: &FilmTile) {
+        for ((x, y), pixel) in film_tile.bounds.pixels().zip(film_tile.pixels.iter()) {
+            self.pixels[(y * self.width + x) as usize].merge(pixel);
+        }
+        for ((x, y), aov) in film_tile.bounds.pixels().zip(film_tile.aovs.iter()) {
+            self.aovs[(y * self.width + x) as usize].merge(aov);
+        }
+    }
+
+    /// adds the samples of a film of the same size
+    pub(crate) fn merge(&mut self, other: &Film) {
+        for (pixel, other_pixel) in self.pixels.iter_mut().zip(other.pixels.iter()) {
+            pixel.merge(other_pixel);
+        }
+        for (aov, other_aov) in self.aovs.iter_mut().zip(other.aovs.iter()) {
+            aov.merge(other_aov);
+        }
+    }
+
+    pub(crate) fn sample_count(&self) -> usize {
+        self.pixels.iter().map(|pixel| pixel.sample_count).sum()
+    }
+
+    /// number of NaN or infinite samples, and of pixels having some
+    pub(crate) fn invalid_sample_count(&self) -> (usize, usize) {
+        count_samples(&self.pixels, |pixel| pixel.invalid_sample_count)
+    }
+
+    /// number of clamped samples, and of pixels having some
+    pub(crate) fn clamped_sample_count(&self) -> (usize, usize) {
+        count_samples(&self.pixels, |pixel| pixel.clamped_sample_count)
+    }
+
+    /// writes the accumulated samples, little endian
+    pub(crate) fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&self.width.to_le_bytes())?;
+        writer.write_all(&self.height.to_le_bytes())?;
+        for pixel in self.pixels.iter() {
+            pixel.write(writer)?;
+        }
+        write_aov_kinds(&self.aov_kinds, writer)?;
+        writer.write_all(&[!self.aovs.is_empty() as u8])?;
+        for aov in self.aovs.iter() {
+            aov.write(writer)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let width = u32::from_le_bytes(read_bytes(reader)?);
+        let height = u32::from_le_bytes(read_bytes(reader)?);
+        let pixels = (0..width * height)
+            .map(|_| Pixel::read(reader))
+            .collect::<std::io::Result<Vec<Pixel>>>()?;
+        let aov_kinds = read_aov_kinds(reader)?;
+        let [capture_aovs] = read_bytes(reader)?;
+        let aov_count = if capture_aovs != 0 { width * height } else { 0 };
+        let aovs = (0..aov_count)
+            .map(|_| AovPixel::read(reader))
+            .collect::<std::io::Result<Vec<AovPixel>>>()?;
+        Ok(Film {
+            width,
+            height,
+            pixels,
+            aov_kinds,
+            aovs,
+        })
+    }
+
+    /// mean color of every pixel
+    pub(crate) fn colors(&self) -> Vec<RRgb> {
+        self.pixels.iter().map(|pixel| pixel.mean()).collect()
+    }
+
+    /// builds the image of the given pixel colors, with the pixels coverage as alpha channel if `alpha`
+    pub(crate) fn to_color_image(
+        &self,
+        colors: &[RRgb],
+        alpha: bool,
+        bit_depth: BitDepth,
+    ) -> DynamicImage {
+        // colors are premultiplied by the coverage, the written alpha is not
+        let straight_color = |index: usize| {
+            let alpha = self.pixels[index].alpha();
+            if alpha > 0. {
+                colors[index].clone() * (1. / alpha) as RT
+            } else {
+                RRgb::default()
+            }
+        };
+        let coverage = |index: usize| self.pixels[index].alpha().clamp(0., 1.);
+        match (bit_depth, alpha) {
+            (BitDepth::Eight, false) => {
+                DynamicImage::ImageRgb8(self.to_image_with(|index| colors[index].clone().into()))
+            }
+            (BitDepth::Eight, true) => DynamicImage::ImageRgba8(self.to_image_with(|index| {
+                let Rgb([r, g, b]) = straight_color(index).into();
+                Rgba([r, g, b, (coverage(index) * 255.).round() as u8])
+            })),
+            (BitDepth::Sixteen, false) => {
+                DynamicImage::ImageRgb16(self.to_image_with(|index| colors[index].clone().into()))
+            }
+            (BitDepth::Sixteen, true) => DynamicImage::ImageRgba16(self.to_image_with(|index| {
+                let Rgb([r, g, b]): Rgb<u16> = straight_color(index).into();
+                Rgba([r, g, b, (coverage(index) * 65535.).round() as u16])
+            })),
+        }
+    }
+
+    /// builds an image locating the problematic samples
+    ///
+    /// Pixels with NaN or infinite samples are red, pixels with clamped samples are yellow, the
+    /// others show the dimmed luminance of the image.
+    pub(crate) fn to_debug_image(&self) -> RgbImage {
+        self.to_image_with(|index| {
+            let pixel = &self.pixels[index];
+            if pixel.invalid_sample_count > 0 {
+                Rgb([255, 0, 0])
+            } else if pixel.clamped_sample_count > 0 {
+                Rgb([255, 255, 0])
+            } else {
+                let luminance = (pixel.mean().luminance().clamp(0., 255.) / 4.) as u8;
+                Rgb([luminance; 3])
+            }
+        })
+    }
+
+    /// builds an image from the color of each pixel index
+    pub(crate) fn to_image_with<P, F>(&self, color: F) -> ImageBuffer<P, Vec<P::Subpixel>>
+    where
+        P: image::Pixel + 'static,
+        F: Fn(usize) -> P,
+    {
+        let mut img = ImageBuffer::new(self.width, self.height);
+        for index in 0..self.pixels.len() {
+            let x = index as u32 % self.width;
+            let y = index as u32 / self.width;
+            let inverted_y = self.height - y - 1; // invert y axis, our raytracer camera y axis points upward, the image crate points downward
+            img.put_pixel(x, inverted_y, color(index));
+        }
+        img
+    }
+}
+
+/// Film region where the samples of a tile are accumulated before being merged into the film.
+///
+/// `bounds` covers the tile and the margin reached by the reconstruction filter.
+pub(crate) struct FilmTile {
+    bounds: Tile,
+    pixels: Vec<Pixel>,
+    /// empty without aov
+    aovs: Vec<AovPixel>,
+    /// samples are scaled down to this luminance
+    max_sample_luminance: Option<f64>,
+}
+
+impl FilmTile {
+    pub(crate) fn new(bounds: Tile, with_aovs: bool, max_sample_luminance: Option<f64>) -> Self {
+        let pixel_count = (bounds.width * bounds.height) as usize;
+        FilmTile {
+            bounds,
+            pixels: vec![Pixel::default(); pixel_count],
+            aovs: vec![AovPixel::default(); if with_aovs { pixel_count } else { 0 }],
+            max_sample_luminance,
+        }
+    }
+
+    /// adds a sample taken within pixel (x, y), at `position` in film pixel coordinates
+    ///
+    /// Aovs are not filtered, they only go to pixel (x, y). NaN or infinite samples are only
+    /// counted, samples brighter than the maximum luminance are scaled down.
+    pub(crate) fn add_sample(
+        &mut self,
+        (x, y): (u32, u32),
+        [sx, sy]: [RT; 2],
+        color: &RRgb,
+        alpha: f64,
+        aov: Option<&AovSample>,
+        filter: &Filter,
+    ) {
+        let bounds = self.bounds;
+        let index = ((y - bounds.y) * bounds.width + x - bounds.x) as usize;
+        if !color.is_finite() {
+            self.pixels[index].add_invalid_sample();
+            return;
+        }
+        let luminance = color.luminance();
+        let (color, clamped) = match self.max_sample_luminance {
+            Some(max) if luminance > max => (color.clone() * (max / luminance) as RT, true),
+            _ => (color.clone(), false),
+        };
+        self.pixels[index].add_sample(&color, clamped);
+        if let (Some(aov_pixel), Some(aov)) = (self.aovs.get_mut(index), aov) {
+            aov_pixel.add_sample(aov);
+        }
+        // pixels whose center is within the filter radius
+        let x0 = ((sx - filter.radius - 0.5).ceil() as i64).max(bounds.x as i64);
+        let x1 =
+            ((sx + filter.radius - 0.5).floor() as i64).min((bounds.x + bounds.width) as i64 - 1);
+        let y0 = ((sy - filter.radius - 0.5).ceil() as i64).max(bounds.y as i64);
+        let y1 =
+            ((sy + filter.radius - 0.5).floor() as i64).min((bounds.y + bounds.height) as i64 - 1);
+        for py in y0..=y1 {
+            for px in x0..=x1 {
+                let weight = filter.weight(px as RT + 0.5 - sx, py as RT + 0.5 - sy);
+                if weight != 0. {
+                    let index = (py - bounds.y as i64) * bounds.width as i64 + px - bounds.x as i64;
+                    self.pixels[index as usize].add_splat(&color, alpha, weight);
+                }
+            }
+        }
+    }
+}
+
+/// sums a count over the pixels, and counts the pixels where it is not 0
+fn count_samples<F: Fn(&Pixel) -> usize>(pixels: &[Pixel], count: F) -> (usize, usize) {
+    pixels
+        .iter()
+        .map(count)
+        .filter(|&count| count > 0)
+        .fold((0, 0), |(samples, pixels), count| {
+            (samples + count, pixels + 1)
+        })
+}
+
+pub(crate) fn read_bytes<R: Read, const N: usize>(reader: &mut R) -> std::io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterKind;
+    use crate::tile::{tiles, TileOrder};
+
+    /// splats 4x4 samples per pixel, some on the pixel boundaries, tile by tile
+    fn splat_film(width: u32, height: u32, tile_size: u32, filter: &Filter) -> Film {
+        let margin = (filter.radius - 0.5).ceil().max(0.) as u32;
+        let mut film = Film::new(width, height, vec![], false);
+        for tile in tiles(width, height, tile_size, TileOrder::Scanline) {
+            let mut film_tile = FilmTile::new(tile.expand(margin, width, height), false, None);
+            for (x, y) in tile.pixels() {
+                for (i, j) in (0..4).flat_map(|i| (0..4).map(move |j| (i, j))) {
+                    let position = [x as RT + i as RT / 4., y as RT + j as RT / 4.];
+                    let color = RRgb::new(1., 2., 3.);
+                    film_tile.add_sample((x, y), position, &color, 1., None, filter);
+                }
+            }
+            film.merge_tile(&film_tile);
+        }
+        film
+    }
+
+    #[test]
+    fn box_splats_are_neither_dropped_nor_double_counted() {
+        let film = splat_film(10, 7, 4, &Filter::default());
+        for pixel in film.pixels() {
+            assert_eq!(pixel.sample_count, 16);
+            assert_eq!(pixel.weight_sum, 16.);
+        }
+    }
+
+    #[test]
+    fn tiles_splat_like_a_single_tile() {
+        for &kind in &[FilterKind::Tent, FilterKind::Mitchell, FilterKind::Lanczos] {
+            let filter = Filter {
+                kind,
+                radius: kind.default_radius(),
+            };
+            let single_tile = splat_film(10, 7, 16, &filter);
+            let tiled = splat_film(10, 7, 3, &filter);
+            for (pixel, tiled_pixel) in single_tile.pixels().iter().zip(tiled.pixels()) {
+                assert!((pixel.weight_sum - tiled_pixel.weight_sum).abs() < 1e-4);
+                // a constant image stays constant, up to the image borders
+                let [r, g, b] = tiled_pixel.mean().to_array();
+                assert!((r - 1.).abs() < 1e-4 && (g - 2.).abs() < 1e-4 && (b - 3.).abs() < 1e-4);
+            }
+        }
+    }
+
+    /// pixel getting gray samples of the given luminances
+    fn pixel_with_samples(luminances: &[f64]) -> Pixel {
+        let bounds = Tile {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        };
+        let mut film_tile = FilmTile::new(bounds, false, None);
+        for &luminance in luminances {
+            let color = RRgb::new(luminance, luminance, luminance);
+            film_tile.add_sample((0, 0), [0.5, 0.5], &color, 1., None, &Filter::default());
+        }
+        film_tile.pixels[0].clone()
+    }
+
+    #[test]
+    fn mean_luminance_variance_of_known_samples() {
+        // sample variance 5/3, divided by the 4 samples
+        let pixel = pixel_with_samples(&[1., 2., 3., 4.]);
+        assert!((pixel.mean_luminance_variance() - 5. / 12.).abs() < 1e-9);
+        assert!((pixel.relative_error() - (5f64 / 12.).sqrt() / 2.5).abs() < 1e-9);
+        // dark pixels are compared to a luminance of 1
+        let dark_pixel = pixel_with_samples(&[0.1, 0.2, 0.3, 0.4]);
+        assert!((dark_pixel.relative_error() - (5f64 / 1200.).sqrt()).abs() < 1e-9);
+        assert_eq!(pixel_with_samples(&[2.; 4]).mean_luminance_variance(), 0.);
+    }
+
+    #[test]
+    fn invalid_samples_are_left_out_of_the_variance() {
+        let pixel = pixel_with_samples(&[1., 2., f64::NAN, 3., 4., f64::INFINITY]);
+        assert_eq!(pixel.sample_count, 6);
+        assert!((pixel.mean_luminance_variance() - 5. / 12.).abs() < 1e-9);
+        assert_eq!(
+            pixel_with_samples(&[1., f64::NAN]).relative_error(),
+            f64::INFINITY
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use crate::ray::RT;
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum FilterKind {
+    /// every sample within the radius has the same weight
+    Box,
+    /// weight decreasing linearly with the distance
+    Tent,
+    /// truncated Gaussian, the standard deviation is a third of the radius
+    Gaussian,
+    /// Mitchell-Netravali cubic with B = C = 1/3
+    Mitchell,
+    /// sinc windowed by a sinc as wide as the radius
+    Lanczos,
+}
+
+impl FilterKind {
+    /// radius used if none is given, in pixels
+    pub(crate) fn default_radius(&self) -> RT {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.,
+            FilterKind::Lanczos => 2.,
+        }
+    }
+}
+
+impl FromStr for FilterKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(FilterKind::Box),
+            "tent" => Ok(FilterKind::Tent),
+            "gaussian" => Ok(FilterKind::Gaussian),
+            "mitchell" => Ok(FilterKind::Mitchell),
+            "lanczos" => Ok(FilterKind::Lanczos),
+            _ => Err(anyhow::anyhow!(
+                "invalid filter {}, expected box, tent, gaussian, mitchell or lanczos",
+                s
+            )),
+        }
+    }
+}
+
+/// Pixel reconstruction filter, each sample is splatted to the pixels whose center is
+/// within `radius` pixels, weighted by the filter.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Filter {
+    pub kind: FilterKind,
+    pub radius: RT,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter {
+            kind: FilterKind::Box,
+            radius: FilterKind::Box.default_radius(),
+        }
+    }
+}
+
+impl Filter {
+    /// weight of a sample at offset (dx, dy) from a pixel center, separable
+    pub(crate) fn weight(&self, dx: RT, dy: RT) -> RT {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    /// weight at offset `x` from a pixel center, over the half-open support (-radius, radius]
+    ///
+    /// Offsets go from the sample to the pixel center, a sample on the boundary of two box
+    /// pixels only goes to the pixel containing it.
+    fn weight_1d(&self, x: RT) -> RT {
+        let radius = self.radius;
+        if x <= -radius || x > radius {
+            return 0.;
+        }
+        let x = x.abs();
+        match self.kind {
+            FilterKind::Box => 1.,
+            FilterKind::Tent => radius - x,
+            FilterKind::Gaussian => {
+                let sigma = radius / 3.;
+                let gaussian = |x: RT| (-x * x / (2. * sigma * sigma)).exp();
+                // shifted so that the weight falls to 0 at the radius
+                gaussian(x) - gaussian(radius)
+            }
+            FilterKind::Mitchell => mitchell(2. * x / radius),
+            FilterKind::Lanczos => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+/// Mitchell-Netravali cubic on [0, 2]
+fn mitchell(x: RT) -> RT {
+    let (b, c) = (1. / 3., 1. / 3.);
+    let x2 = x * x;
+    let x3 = x2 * x;
+    if x < 1. {
+        ((12. - 9. * b - 6. * c) * x3 + (-18. + 12. * b + 6. * c) * x2 + (6. - 2. * b)) / 6.
+    } else {
+        ((-b - 6. * c) * x3
+            + (6. * b + 30. * c) * x2
+            + (-12. * b - 48. * c) * x
+            + (8. * b + 24. * c))
+            / 6.
+    }
+}
+
+fn sinc(x: RT) -> RT {
+    if x.abs() < 1e-5 {
+        1.
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [FilterKind; 5] = [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::Mitchell,
+        FilterKind::Lanczos,
+    ];
+
+    fn filter(kind: FilterKind) -> Filter {
+        Filter {
+            kind,
+            radius: kind.default_radius(),
+        }
+    }
+
+    #[test]
+    fn mitchell_and_lanczos_at_center_and_radius() {
+        let mitchell = filter(FilterKind::Mitchell);
+        assert!((mitchell.weight_1d(0.) - 8. / 9.).abs() < 1e-6);
+        assert!(mitchell.weight_1d(mitchell.radius).abs() < 1e-6);
+        assert_eq!(mitchell.weight_1d(-mitchell.radius), 0.);
+        let lanczos = filter(FilterKind::Lanczos);
+        assert_eq!(lanczos.weight_1d(0.), 1.);
+        assert!(lanczos.weight_1d(lanczos.radius).abs() < 1e-6);
+        assert_eq!(lanczos.weight_1d(-lanczos.radius), 0.);
+    }
+
+    #[test]
+    fn box_sample_goes_to_one_pixel() {
+        let filter = Filter::default();
+        // sample at pixel centers offsets, including the boundaries between pixels
+        for offset in &[0., 0.25, 0.5, 0.75, 0.999] {
+            let total: RT = (-2..=2)
+                .map(|pixel| filter.weight_1d(pixel as RT - offset))
+                .sum();
+            assert_eq!(total, 1., "sample at offset {}", offset);
+        }
+    }
+
+    /// the film divides by the weight sum, so weights only matter relative to their integral
+    #[test]
+    fn normalized_weights_sum_to_one_over_pixels() {
+        let steps = 10000;
+        for &kind in KINDS.iter() {
+            let filter = filter(kind);
+            let dx = 2. * filter.radius as f64 / steps as f64;
+            let integral: f64 = (0..steps)
+                .map(|i| -filter.radius as f64 + (i as f64 + 0.5) * dx)
+                .map(|x| filter.weight_1d(x as RT) as f64 * dx)
+                .sum();
+            // a sample anywhere in a pixel gets the same total weight over the pixel grid
+            for offset in &[0., 0.1, 0.25, 0.5, 0.9] {
+                let total: f64 = (-4..=4)
+                    .map(|pixel| filter.weight_1d(pixel as RT - offset) as f64 / integral)
+                    .sum();
+                // exact for box, tent and mitchell, within a few percent for the others
+                assert!(
+                    (total - 1.).abs() < 0.025,
+                    "{:?} sample at offset {}: total weight {}",
+                    kind,
+                    offset,
+                    total
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::str::FromStr;
+
+/// Fraunhofer lines the Abbe number is defined with, in nanometers.
+pub(crate) const D_LINE: f64 = 587.56;
+const F_LINE: f64 = 486.13;
+const C_LINE: f64 = 656.27;
+
+/// Index of refraction of a dielectric as a function of the wavelength.
+///
+/// Wavelengths are in micrometers in the formulas, as in glass catalogs.
+#[derive(Clone, Debug)]
+pub(crate) enum Ior {
+    /// same index for every wavelength, no dispersion
+    Constant(f64),
+    /// n = a + b / λ²
+    Cauchy { a: f64, b: f64 },
+    /// n² = 1 + Σ b λ² / (λ² - c)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Ior {
+    /// Cauchy model with the index `nd` at the d line and the Abbe number `vd`
+    ///
+    /// The lower the Abbe number, the stronger the dispersion.
+    pub(crate) fn from_abbe(nd: f64, vd: f64) -> Self {
+        let inverse_squared = |lambda: f64| 1. / (lambda * 1e-3).powi(2);
+        let b = (nd - 1.) / (vd * (inverse_squared(F_LINE) - inverse_squared(C_LINE)));
+        Ior::Cauchy {
+            a: nd - b * inverse_squared(D_LINE),
+            b,
+        }
+    }
+
+    /// index of refraction at `lambda` nanometers
+    pub(crate) fn at(&self, lambda: f64) -> f64 {
+        let l2 = (lambda * 1e-3).powi(2);
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => {
+                (1. + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()).sqrt()
+            }
+        }
+    }
+
+    /// whether the index depends on the wavelength
+    pub(crate) fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+
+    /// indices at the F, d and C lines, they identify the material
+    pub(crate) fn fraunhofer_indices(&self) -> [f64; 3] {
+        [F_LINE, D_LINE, C_LINE].map(|lambda| self.at(lambda))
+    }
+}
+
+impl FromStr for Ior {
+    type Err = anyhow::Error;
+
+    /// `N`, a preset, `cauchy:A,B`, `sellmeier:B1,B2,B3,C1,C2,C3` or `abbe:ND,VD`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(n) = s.parse::<f64>() {
+            return if n > 0. {
+                Ok(Ior::Constant(n))
+            } else {
+                Err(anyhow::anyhow!("index of refraction should be > 0"))
+            };
+        }
+        let mut parts = s.splitn(2, ':');
+        let kind = parts.next().unwrap_or_default();
+        let args = match parts.next() {
+            Some(args) => args
+                .split(',')
+                .map(|arg| arg.trim().parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()?,
+            None => vec![],
+        };
+        match (kind, args.as_slice()) {
+            // Schott N-BK7
+            ("crown", []) => Ok(Ior::Sellmeier {
+                b: [1.039_612_12, 0.231_792_344, 1.010_469_45],
+                c: [0.006_000_698_67, 0.020_017_914_4, 103.560_653],
+            }),
+            // Schott SF11
+            ("flint", []) => Ok(Ior::Sellmeier {
+                b: [1.737_596_95, 0.313_747_346, 1.898_781_01],
+                c: [0.013_188_707, 0.062_306_814_2, 155.236_29],
+            }),
+            // Malitson
+            ("fused-silica", []) => Ok(Ior::Sellmeier {
+                b: [0.696_166_3, 0.407_942_6, 0.897_479_4],
+                c: [0.004_679_148, 0.013_512_063, 97.934_003],
+            }),
+            ("diamond", []) => Ok(Ior::from_abbe(2.4175, 55.3)),
+            ("water", []) => Ok(Ior::from_abbe(1.333, 55.8)),
+            ("cauchy", &[a, b]) => Ok(Ior::Cauchy { a, b }),
+            ("sellmeier", &[b1, b2, b3, c1, c2, c3]) => Ok(Ior::Sellmeier {
+                b: [b1, b2, b3],
+                c: [c1, c2, c3],
+            }),
+            ("abbe", &[nd, vd]) if nd > 1. && vd > 0. => Ok(Ior::from_abbe(nd, vd)),
+            _ => Err(anyhow::anyhow!(
+                "invalid index of refraction {}, expected a number, crown, flint, fused-silica, diamond, water, cauchy:A,B, sellmeier:B1,B2,B3,C1,C2,C3 or abbe:ND,VD",
+                s
+            )),
+        }
+    }
+}
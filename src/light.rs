@@ -0,0 +1,207 @@
+use crate::cli::parse_triplet;
+use crate::material::{Emission, Light};
+use crate::ray::RT;
+use crate::spectrum::{Illuminant, Spectrum};
+use nalgebra::{Point3, Vector3};
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// Light source without surface, it cannot be hit by rays and is sampled at each diffuse hit.
+///
+/// The emission is an intensity in candela for point and spot lights, an illuminance in lux
+/// for directional lights.
+#[derive(Clone, Debug)]
+pub(crate) enum PunctualLight {
+    /// emits in every direction
+    Point {
+        position: Point3<RT>,
+        intensity: Light,
+    },
+    /// emits within a cone, fully inside `cos_inner` and fading out up to `cos_outer`
+    Spot {
+        position: Point3<RT>,
+        /// normalized axis of the cone
+        direction: Vector3<RT>,
+        cos_inner: RT,
+        cos_outer: RT,
+        intensity: Light,
+    },
+    /// parallel light from infinitely far away, like the sun
+    Directional {
+        /// normalized direction the light travels
+        direction: Vector3<RT>,
+        illuminance: Light,
+    },
+}
+
+impl PunctualLight {
+    /// returns the normalized direction towards the light, its distance and the factor
+    /// turning its emission into the irradiance at `point` under normal incidence
+    pub(crate) fn illuminate(&self, point: &Point3<RT>) -> Option<(Vector3<RT>, RT, RT)> {
+        match self {
+            PunctualLight::Point { position, .. } => {
+                let to_light = position - point;
+                let distance = to_light.norm();
+                Some((to_light / distance, distance, 1. / (distance * distance)))
+            }
+            PunctualLight::Spot {
+                position,
+                direction,
+                cos_inner,
+                cos_outer,
+                ..
+            } => {
+                let to_light = position - point;
+                let distance = to_light.norm();
+                let to_light = to_light / distance;
+                let cos_theta = -to_light.dot(direction);
+                if cos_theta <= *cos_outer {
+                    return None;
+                }
+                let falloff = smoothstep(*cos_outer, *cos_inner, cos_theta);
+                Some((to_light, distance, falloff / (distance * distance)))
+            }
+            PunctualLight::Directional { direction, .. } => Some((-direction, RT::INFINITY, 1.)),
+        }
+    }
+
+    /// color and amount of the emitted light
+    pub(crate) fn emitter(&self) -> &Light {
+        match self {
+            PunctualLight::Point { intensity, .. } => intensity,
+            PunctualLight::Spot { intensity, .. } => intensity,
+            PunctualLight::Directional { illuminance, .. } => illuminance,
+        }
+    }
+}
+
+fn smoothstep(edge0: RT, edge1: RT, x: RT) -> RT {
+    if edge0 >= edge1 {
+        return if x >= edge1 { 1. } else { 0. };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+/// intensity in candela of `amount` (cd, lm or W) spread over `solid_angle`
+fn parse_intensity(amount: &str, spectrum: &Spectrum, solid_angle: f64) -> anyhow::Result<f64> {
+    if let Some(candela) = amount.strip_suffix("cd") {
+        return match candela.trim().parse::<f64>() {
+            Ok(candela) if candela >= 0. => Ok(candela),
+            _ => Err(anyhow::anyhow!("invalid intensity {}", amount)),
+        };
+    }
+    match amount.parse::<Emission>()? {
+        Emission::Lumens(lumens) => Ok(lumens / solid_angle),
+        Emission::Watts(watts) => Ok(watts * spectrum.luminous_efficacy() / solid_angle),
+        Emission::Luminance(_) => Err(anyhow::anyhow!(
+            "point and spot lights emit cd, lm or W, not {}",
+            amount
+        )),
+    }
+}
+
+impl FromStr for PunctualLight {
+    type Err = anyhow::Error;
+
+    /// `point:X,Y,Z:AMOUNT[:SPECTRUM]`, `spot:X,Y,Z:TX,TY,TZ:INNER,OUTER:AMOUNT[:SPECTRUM]`
+    /// or `directional:DX,DY,DZ:LUXlx[:SPECTRUM]`, the spectrum being the last argument
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            anyhow::anyhow!(
+                "invalid light {}, expected point:X,Y,Z:AMOUNT[:SPECTRUM], spot:X,Y,Z:TX,TY,TZ:INNER,OUTER:AMOUNT[:SPECTRUM] or directional:DX,DY,DZ:LUXlx[:SPECTRUM]",
+                s
+            )
+        };
+        let kind = s.split(':').next().unwrap_or_default();
+        let argument_count = match kind {
+            "point" | "directional" => 2,
+            "spot" => 4,
+            _ => return Err(invalid()),
+        };
+        let parts: Vec<&str> = s.splitn(argument_count + 2, ':').skip(1).collect();
+        if parts.len() < argument_count {
+            return Err(invalid());
+        }
+        let spectrum = match parts.get(argument_count) {
+            Some(spectrum) => Some(spectrum.parse::<Spectrum>()?),
+            None => None,
+        };
+        // white lights have the spectrum of the white of sRGB
+        let efficacy_spectrum = spectrum.unwrap_or(Spectrum::Illuminant(Illuminant::D65));
+        let amount = parts[argument_count - 1].trim();
+        match kind {
+            "point" => {
+                let [x, y, z] = parse_triplet(parts[0])?;
+                let intensity = parse_intensity(amount, &efficacy_spectrum, 4. * PI)?;
+                Ok(PunctualLight::Point {
+                    position: Point3::new(x, y, z),
+                    intensity: Light::new(spectrum, intensity),
+                })
+            }
+            "directional" => {
+                let [x, y, z] = parse_triplet(parts[0])?;
+                let direction = Vector3::new(x, y, z);
+                if direction.norm() == 0. {
+                    return Err(anyhow::anyhow!(
+                        "directional light direction should not be 0"
+                    ));
+                }
+                let illuminance = match amount.strip_suffix("lx").map(|lux| lux.trim().parse()) {
+                    Some(Ok(lux)) if lux >= 0. => lux,
+                    _ => {
+                        return Err(anyhow::anyhow!(
+                            "directional lights emit lx, not {}",
+                            amount
+                        ))
+                    }
+                };
+                Ok(PunctualLight::Directional {
+                    direction: direction.normalize(),
+                    illuminance: Light::new(spectrum, illuminance),
+                })
+            }
+            _ => {
+                let [x, y, z] = parse_triplet(parts[0])?;
+                let [tx, ty, tz] = parse_triplet(parts[1])?;
+                let position = Point3::new(x, y, z);
+                let direction = Point3::new(tx, ty, tz) - position;
+                if direction.norm() == 0. {
+                    return Err(anyhow::anyhow!(
+                        "spot light target should differ from its position"
+                    ));
+                }
+                let angles = parts[2]
+                    .split(',')
+                    .map(|angle| angle.trim().parse::<RT>())
+                    .collect::<Result<Vec<RT>, _>>()?;
+                let (inner, outer) = match angles.as_slice() {
+                    &[inner, outer]
+                        if 0. <= inner && inner <= outer && 0. < outer && outer < 180. =>
+                    {
+                        (inner, outer)
+                    }
+                    _ => {
+                        return Err(anyhow::anyhow!(
+                        "spot light angles should be 0 <= INNER <= OUTER < 180 degrees, OUTER > 0"
+                    ))
+                    }
+                };
+                // full apertures of the cone
+                let cos_inner = (inner / 2.).to_radians().cos();
+                let cos_outer = (outer / 2.).to_radians().cos();
+                // the falloff counts for half
+                let solid_angle =
+                    2. * PI * ((1. - cos_inner as f64) + (cos_inner - cos_outer) as f64 / 2.);
+                let intensity = parse_intensity(amount, &efficacy_spectrum, solid_angle)?;
+                Ok(PunctualLight::Spot {
+                    position,
+                    direction: direction.normalize(),
+                    cos_inner,
+                    cos_outer,
+                    intensity: Light::new(spectrum, intensity),
+                })
+            }
+        }
+    }
+}
@@ -1,16 +1,21 @@
 use crate::color::RRgb;
+use crate::ior::{Ior, D_LINE};
 use crate::ray::{random_in_unit_sphere, Ray, RayHit, RT};
+use crate::sampler::Sampler;
+use crate::spectrum::{Illuminant, SampledSpectrum, SampledWavelengths, ScaledSpectrum, Spectrum};
+use crate::texture::Texture;
 use nalgebra::Vector3;
-use rand::distributions::Uniform;
-use rand::prelude::ThreadRng;
-use rand::Rng;
+use std::f64::consts::PI;
+use std::str::FromStr;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub(crate) enum Material {
     Dieletric(Dieletric),
     Lambertian(Lambertian),
     Metal(Metal),
     Light(Light),
+    /// black and transparent in the alpha channel, it only masks what is behind
+    Holdout,
 }
 
 impl Scatterer for Material {
@@ -18,54 +23,229 @@ impl Scatterer for Material {
         &self,
         ray: &Ray<f32>,
         ray_hit: &RayHit,
-        thread_rng: &mut ThreadRng,
+        sampler: &mut Sampler,
+        wavelengths: Option<&mut SampledWavelengths>,
     ) -> Option<(RRgb, Ray<f32>)> {
         match self {
-            Material::Dieletric(dieletric) => dieletric.scatter(ray, ray_hit, thread_rng),
-            Material::Lambertian(lambertian) => lambertian.scatter(ray, ray_hit, thread_rng),
-            Material::Metal(metal) => metal.scatter(ray, ray_hit, thread_rng),
+            Material::Dieletric(dieletric) => dieletric.scatter(ray, ray_hit, sampler, wavelengths),
+            Material::Lambertian(lambertian) => {
+                lambertian.scatter(ray, ray_hit, sampler, wavelengths)
+            }
+            Material::Metal(metal) => metal.scatter(ray, ray_hit, sampler, wavelengths),
             Material::Light(_) => None, // does not scatter light
+            Material::Holdout => None,
+        }
+    }
+}
+
+impl Material {
+    /// surface color under white light, for the albedo output
+    pub(crate) fn albedo(&self) -> RRgb {
+        match self {
+            Material::Dieletric(_) => RRgb::new(1., 1., 1.),
+            Material::Lambertian(lambertian) => lambertian.albedo.clone(),
+            Material::Metal(metal) => metal.albedo.clone(),
+            Material::Light(light) => {
+                // emitted color normalized to its brightest component
+                let [r, g, b] = light.emitted.to_array();
+                let max = r.max(g).max(b);
+                if max > 0. {
+                    RRgb::new(r / max, g / max, b / max)
+                } else {
+                    RRgb::default()
+                }
+            }
+            Material::Holdout => RRgb::default(),
         }
     }
 }
 
 impl Emitter for Material {
-    fn emit(&self) -> RRgb {
+    fn emit(&self, ray_hit: &RayHit) -> RRgb {
         match self {
             Material::Dieletric(_) => RRgb::new(0., 0., 0.),
             Material::Lambertian(_) => RRgb::new(0., 0., 0.),
             Material::Metal(_) => RRgb::new(0., 0., 0.),
-            Material::Light(light) => light.emit(),
+            Material::Light(light) => light.emit(ray_hit),
+            Material::Holdout => RRgb::new(0., 0., 0.),
+        }
+    }
+
+    fn emit_spectrum(&self, ray_hit: &RayHit, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        match self {
+            Material::Light(light) => light.emit_spectrum(ray_hit, wavelengths),
+            _ => SampledSpectrum::default(),
         }
     }
 }
 
 pub(crate) trait Scatterer {
     /// returns color attenuation and scattered ray
+    ///
+    /// `wavelengths` are the wavelengths of a spectral path, a scattering depending on the
+    /// wavelength only keeps the hero one.
     fn scatter(
         &self,
         ray: &Ray<RT>,
         ray_hit: &RayHit,
-        thread_rng: &mut ThreadRng,
+        sampler: &mut Sampler,
+        wavelengths: Option<&mut SampledWavelengths>,
     ) -> Option<(RRgb, Ray<RT>)>;
 }
 
 pub(crate) trait Emitter {
-    fn emit(&self) -> RRgb;
+    /// light emitted by the point hit by `ray_hit`, towards the origin of the ray
+    fn emit(&self, ray_hit: &RayHit) -> RRgb;
+
+    /// emission at the wavelengths of a spectral path, upsampled from the RGB emission by default
+    fn emit_spectrum(&self, ray_hit: &RayHit, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        SampledSpectrum::illuminant(&self.emit(ray_hit), wavelengths)
+    }
 }
 
-#[derive(Clone)]
+/// Amount of light emitted by a light.
+///
+/// Radiance is in cd/m² for its luminance, 255 being the white of the image.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Emission {
+    /// luminance of the surface, in cd/m²
+    Luminance(f64),
+    /// luminous flux, in lumens
+    Lumens(f64),
+    /// radiant flux, in watts
+    Watts(f64),
+}
+
+impl Emission {
+    /// luminance of a diffuse emitter of the given area, emitting `spectrum`
+    pub(crate) fn luminance(&self, spectrum: &Spectrum, area: f64) -> f64 {
+        // the flux of a diffuse surface is π times its luminance, per unit area
+        match self {
+            Emission::Luminance(luminance) => *luminance,
+            Emission::Lumens(lumens) => lumens / (PI * area),
+            Emission::Watts(watts) => watts * spectrum.luminous_efficacy() / (PI * area),
+        }
+    }
+}
+
+impl FromStr for Emission {
+    type Err = anyhow::Error;
+
+    /// `POWERW`, `POWERlm` or `LUMINANCEnit`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            anyhow::anyhow!(
+                "invalid light emission {}, expected POWERW, POWERlm or LUMINANCEnit",
+                s
+            )
+        };
+        let (amount, emission): (&str, fn(f64) -> Emission) =
+            if let Some(watts) = s.strip_suffix('W') {
+                (watts, Emission::Watts)
+            } else if let Some(lumens) = s.strip_suffix("lm") {
+                (lumens, Emission::Lumens)
+            } else if let Some(luminance) = s.strip_suffix("nit") {
+                (luminance, Emission::Luminance)
+            } else {
+                return Err(invalid());
+            };
+        match amount.trim().parse::<f64>() {
+            Ok(amount) if amount >= 0. => Ok(emission(amount)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub(crate) struct Light {
+    /// emitted color, the color of `spectrum` if any
     pub emitted: RRgb,
+    /// emission of spectral renders, none to upsample `emitted`
+    pub spectrum: Option<ScaledSpectrum>,
+    /// modulates the emission over the surface (screens, signs), uniform if none
+    pub texture: Option<Texture>,
+    /// whether the back faces emit too, a one-sided light only emits on the side its normals face
+    pub two_sided: bool,
+}
+
+impl Light {
+    /// light emitting the given spectrum
+    pub(crate) fn spectral(spectrum: ScaledSpectrum) -> Self {
+        Light {
+            emitted: spectrum.to_rgb(),
+            spectrum: Some(spectrum),
+            texture: None,
+            two_sided: true,
+        }
+    }
+
+    /// light emitting `spectrum`, white (D65) if none, scaled to the photometric `amount`
+    pub(crate) fn new(spectrum: Option<Spectrum>, amount: f64) -> Self {
+        match spectrum {
+            Some(spectrum) => Light::spectral(ScaledSpectrum::new(spectrum, amount)),
+            None => Light {
+                emitted: RRgb::new(amount, amount, amount),
+                spectrum: None,
+                texture: None,
+                two_sided: true,
+            },
+        }
+    }
+
+    /// diffuse light of the given area, emitting `spectrum`, white (D65) if none
+    pub(crate) fn with_emission(spectrum: Option<Spectrum>, emission: Emission, area: f64) -> Self {
+        let white = Spectrum::Illuminant(Illuminant::D65);
+        let luminance = emission.luminance(spectrum.as_ref().unwrap_or(&white), area);
+        Light::new(spectrum, luminance)
+    }
+
+    pub(crate) fn with_texture(self, texture: Option<Texture>) -> Self {
+        Light { texture, ..self }
+    }
+
+    pub(crate) fn with_two_sided(self, two_sided: bool) -> Self {
+        Light { two_sided, ..self }
+    }
+
+    /// emission at the wavelengths of a spectral path, before the texture
+    pub(crate) fn sample(&self, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        match &self.spectrum {
+            Some(spectrum) => spectrum.sample(wavelengths),
+            None => SampledSpectrum::illuminant(&self.emitted, wavelengths),
+        }
+    }
+
+    fn is_emitting(&self, ray_hit: &RayHit) -> bool {
+        self.two_sided || ray_hit.front_face
+    }
 }
 
 impl Emitter for Light {
-    fn emit(&self) -> RRgb {
-        self.emitted.clone()
+    fn emit(&self, ray_hit: &RayHit) -> RRgb {
+        if !self.is_emitting(ray_hit) {
+            return RRgb::default();
+        }
+        match &self.texture {
+            Some(texture) => self.emitted.clone() * texture.value(ray_hit.uv),
+            None => self.emitted.clone(),
+        }
+    }
+
+    fn emit_spectrum(&self, ray_hit: &RayHit, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        if !self.is_emitting(ray_hit) {
+            return SampledSpectrum::default();
+        }
+        match &self.texture {
+            Some(texture) => {
+                self.sample(wavelengths)
+                    * SampledSpectrum::reflectance(&texture.value(ray_hit.uv), wavelengths)
+            }
+            None => self.sample(wavelengths),
+        }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub(crate) struct Lambertian {
     pub albedo: RRgb,
 }
@@ -75,15 +255,18 @@ impl Scatterer for Lambertian {
         &self,
         _ray: &Ray<f32>,
         ray_hit: &RayHit,
-        thread_rng: &mut ThreadRng,
+        sampler: &mut Sampler,
+        _wavelengths: Option<&mut SampledWavelengths>,
     ) -> Option<(RRgb, Ray<f32>)> {
-        let scatter_direction = ray_hit.normal + random_in_unit_sphere(thread_rng);
+        let [u, v] = sampler.get_2d();
+        let w = sampler.get_1d();
+        let scatter_direction = ray_hit.normal + random_in_unit_sphere([u, v, w]);
         let scattered = Ray::new(ray_hit.point, scatter_direction);
         Some((self.albedo.clone(), scattered))
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub(crate) struct Metal {
     pub albedo: RRgb,
 }
@@ -97,7 +280,8 @@ impl Scatterer for Metal {
         &self,
         ray: &Ray<f32>,
         ray_hit: &RayHit,
-        _thread_rng: &mut ThreadRng,
+        _sampler: &mut Sampler,
+        _wavelengths: Option<&mut SampledWavelengths>,
     ) -> Option<(RRgb, Ray<f32>)> {
         let reflected = reflect(&ray.direction().normalize(), &ray_hit.normal);
         let scattered = Ray::new(ray_hit.point, reflected);
@@ -116,9 +300,23 @@ fn refract(uv: &Vector3<RT>, normal: &Vector3<RT>, etai_over_eta: RT) -> Vector3
     r_out_perp + r_out_parallel
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub(crate) struct Dieletric {
-    pub refraction_index: f64,
+    /// dispersion only shows in spectral renders, RGB renders use the index at the d line
+    pub refraction_index: Ior,
+    /// Beer-Lambert absorption coefficient of the medium inside, per unit of distance
+    pub absorption: RRgb,
+}
+
+impl Dieletric {
+    /// fraction of the light left after traveling `distance` inside the medium
+    fn transmittance(&self, distance: f64) -> RRgb {
+        let [r, g, b] = self
+            .absorption
+            .to_array()
+            .map(|coefficient| (-coefficient * distance).exp());
+        RRgb::new(r, g, b)
+    }
 }
 
 fn schlick(cosine: f64, refraction_index: f64) -> f64 {
@@ -132,13 +330,27 @@ impl Scatterer for Dieletric {
         &self,
         ray: &Ray<f32>,
         ray_hit: &RayHit,
-        thread_rng: &mut ThreadRng,
+        sampler: &mut Sampler,
+        wavelengths: Option<&mut SampledWavelengths>,
     ) -> Option<(RRgb, Ray<f32>)> {
-        let attenuation = RRgb::new(1f64, 1f64, 1f64);
+        let attenuation = if ray_hit.front_face {
+            RRgb::new(1f64, 1f64, 1f64)
+        } else {
+            // the ray comes from inside the medium, it was absorbed along its way
+            self.transmittance((ray_hit.t * ray.direction().norm()) as f64)
+        };
+        let refraction_index = match wavelengths {
+            Some(wavelengths) if self.refraction_index.is_dispersive() => {
+                // each wavelength is refracted in its own direction
+                wavelengths.terminate_secondary();
+                self.refraction_index.at(wavelengths.hero())
+            }
+            _ => self.refraction_index.at(D_LINE),
+        };
         let etai_over_etat = if ray_hit.front_face {
-            1f64 / self.refraction_index
+            1f64 / refraction_index
         } else {
-            self.refraction_index
+            refraction_index
         };
 
         let unit_direction = ray.direction().normalize();
@@ -147,8 +359,7 @@ impl Scatterer for Dieletric {
         let sin_theta = (1f64 - cos_theta * cos_theta).sqrt();
 
         let reflected_probability = schlick(cos_theta, etai_over_etat);
-        let side = Uniform::new(0., 1.);
-        let randomly_reflected = thread_rng.sample(side) < reflected_probability;
+        let randomly_reflected = (sampler.get_1d() as f64) < reflected_probability;
 
         let scattered = if randomly_reflected || etai_over_etat * sin_theta > 1f64 {
             // reflected
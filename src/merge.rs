@@ -0,0 +1,187 @@
+use crate::aov::{aov_file_path, aov_image};
+use crate::checkpoint::Checkpoint;
+use crate::denoise::denoise;
+use crate::output::{save_image, BitDepth};
+use crate::render::print_sample_summary;
+
+/// combines the accumulation files of the shards of a render and writes the final image
+///
+/// Pixels sum the samples of every shard, each shard is weighted by its sample count. Every
+/// shard of the render must be given once, unless `allow_partial` lets some of them miss.
+pub(crate) fn merge_shards(
+    shard_paths: &[&str],
+    output_file_path: &str,
+    denoise_image: bool,
+    allow_partial: bool,
+    alpha: bool,
+    bit_depth: BitDepth,
+) -> anyhow::Result<()> {
+    let (first_path, other_paths) = match shard_paths.split_first() {
+        Some(paths) => paths,
+        None => return Err(anyhow::anyhow!("merge needs at least one shard")),
+    };
+    let Checkpoint { header, mut film } = Checkpoint::open(first_path)?;
+    let mut merged_shards = vec![false; header.shard_count as usize];
+    merged_shards[header.shard_index as usize] = true;
+    for shard_path in other_paths {
+        let shard = Checkpoint::open(shard_path)?;
+        if shard.header.seed != header.seed
+            || shard.header.config_hash != header.config_hash
+            || shard.header.scene_hash != header.scene_hash
+        {
+            return Err(anyhow::anyhow!(
+                "{} was not rendered with the same scene and settings as {}",
+                shard_path,
+                first_path
+            ));
+        }
+        if (shard.film.width(), shard.film.height()) != (film.width(), film.height()) {
+            return Err(anyhow::anyhow!(
+                "{} and {} have different sizes",
+                shard_path,
+                first_path
+            ));
+        }
+        if shard.header.shard_count != header.shard_count {
+            return Err(anyhow::anyhow!(
+                "{} is a shard of {} and {} a shard of {}",
+                shard_path,
+                shard.header.shard_count,
+                first_path,
+                header.shard_count
+            ));
+        }
+        if shard.header.sample_per_pixel != header.sample_per_pixel {
+            return Err(anyhow::anyhow!(
+                "{} was rendered with {} samples per pixel and {} with {}, their samples overlap",
+                shard_path,
+                shard.header.sample_per_pixel,
+                first_path,
+                header.sample_per_pixel
+            ));
+        }
+        if shard.header.sampler_strata != header.sampler_strata {
+            return Err(anyhow::anyhow!(
+                "{} and {} were rendered with different sampler strata",
+                shard_path,
+                first_path
+            ));
+        }
+        let shard_index = shard.header.shard_index as usize;
+        if merged_shards[shard_index] {
+            return Err(anyhow::anyhow!(
+                "shard {}/{} is given more than once",
+                shard_index,
+                header.shard_count
+            ));
+        }
+        merged_shards[shard_index] = true;
+        film.merge(&shard.film);
+    }
+    let missing_shards: Vec<String> = (0..merged_shards.len())
+        .filter(|&index| !merged_shards[index])
+        .map(|index| index.to_string())
+        .collect();
+    if !missing_shards.is_empty() && !allow_partial {
+        return Err(anyhow::anyhow!(
+            "missing shards {} of {}, use --allow-partial to merge anyway",
+            missing_shards.join(", "),
+            header.shard_count
+        ));
+    }
+    print_sample_summary(&film);
+    let pixel_count = film.pixels().len();
+    let metadata = vec![
+        ("Software", String::from(crate_name!())),
+        (
+            "Samples per pixel",
+            format!("{:.2}", film.sample_count() as f64 / pixel_count as f64),
+        ),
+    ];
+    let colors = if denoise_image {
+        if film.aovs().is_empty() {
+            return Err(anyhow::anyhow!(
+                "the shards were rendered without --denoise, they lack the aovs guiding the denoiser"
+            ));
+        }
+        denoise(&film)
+    } else {
+        film.colors()
+    };
+    save_image(
+        &film.to_color_image(&colors, alpha, bit_depth),
+        output_file_path,
+        &metadata,
+    )?;
+    for &aov in film.aov_kinds() {
+        let (img, range) = aov_image(&film, aov, bit_depth);
+        let mut metadata = metadata.clone();
+        if let Some(range) = range {
+            metadata.push(("Range", range));
+        }
+        save_image(&img, &aov_file_path(output_file_path, aov), &metadata)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::CheckpointHeader;
+    use crate::film::Film;
+
+    /// writes shard `shard_index` of `shard_count` of an empty render, returns its path
+    fn save_shard(name: &str, shard_index: u64, shard_count: u64, sample_per_pixel: u64) -> String {
+        let path = std::env::temp_dir()
+            .join(format!("rray-merge-{}-{}.bin", std::process::id(), name))
+            .to_string_lossy()
+            .into_owned();
+        let header = CheckpointHeader {
+            seed: 0,
+            config_hash: 1,
+            scene_hash: 2,
+            pass_index: 1,
+            shard_index,
+            shard_count,
+            sample_per_pixel,
+            sampler_strata: 0,
+        };
+        Checkpoint::save(&path, &header, &Film::new(4, 4, vec![], false)).unwrap();
+        path
+    }
+
+    fn merge(paths: &[&str], allow_partial: bool) -> anyhow::Result<()> {
+        let output = std::env::temp_dir()
+            .join(format!("rray-merge-{}.png", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        merge_shards(paths, &output, false, allow_partial, false, BitDepth::Eight)
+    }
+
+    #[test]
+    fn duplicate_shard_is_rejected() {
+        let shard = save_shard("duplicate", 0, 2, 8);
+        assert!(merge(&[&shard, &shard], true).is_err());
+    }
+
+    #[test]
+    fn missing_shard_needs_allow_partial() {
+        let shard = save_shard("missing", 1, 2, 8);
+        assert!(merge(&[&shard], false).is_err());
+        assert!(merge(&[&shard], true).is_ok());
+    }
+
+    #[test]
+    fn mixed_shard_counts_are_rejected() {
+        let first = save_shard("count-2", 0, 2, 8);
+        let second = save_shard("count-3", 1, 3, 8);
+        assert!(merge(&[&first, &second], true).is_err());
+    }
+
+    #[test]
+    fn mixed_sample_per_pixel_is_rejected() {
+        let first = save_shard("spp-8", 0, 2, 8);
+        let second = save_shard("spp-16", 1, 2, 16);
+        assert!(merge(&[&first, &second], false).is_err());
+    }
+}
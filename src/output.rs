@@ -0,0 +1,166 @@
+use image::{ColorType, DynamicImage, GenericImageView, ImageFormat};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Bits per channel of the written images.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum BitDepth {
+    Eight,
+    /// only PNG and TIFF
+    Sixteen,
+}
+
+impl FromStr for BitDepth {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(BitDepth::Eight),
+            "16" => Ok(BitDepth::Sixteen),
+            _ => Err(anyhow::anyhow!("invalid bit depth {}, expected 8 or 16", s)),
+        }
+    }
+}
+
+/// writes the image to `path`, the format is deduced from the extension
+///
+/// `metadata` (key, text) pairs are stored as PNG text chunks, they are ignored by other formats.
+/// The image goes through a temporary file, an interrupted render never leaves a truncated image.
+/// Alpha channels and 16 bits channels are rejected by the formats that would drop them.
+pub(crate) fn save_image(
+    img: &DynamicImage,
+    path: &str,
+    metadata: &[(&str, String)],
+) -> anyhow::Result<()> {
+    let format = ImageFormat::from_path(path)?;
+    let bit_depth = if matches!(img.color(), ColorType::Rgb16 | ColorType::Rgba16) {
+        BitDepth::Sixteen
+    } else {
+        BitDepth::Eight
+    };
+    check_output_format(
+        path,
+        matches!(img.color(), ColorType::Rgba8 | ColorType::Rgba16),
+        bit_depth,
+    )?;
+    let partial_path = format!("{}.partial", path);
+    match format {
+        ImageFormat::Png => save_png(img, &partial_path, metadata)?,
+        _ => img.save_with_format(&partial_path, format)?,
+    }
+    std::fs::rename(&partial_path, path)?;
+    Ok(())
+}
+
+/// checks that an image with or without `alpha`, of `bit_depth`, can be written to `path`,
+/// before rendering it
+pub(crate) fn check_output_format(
+    path: &str,
+    alpha: bool,
+    bit_depth: BitDepth,
+) -> anyhow::Result<()> {
+    let format = ImageFormat::from_path(path)?;
+    if bit_depth == BitDepth::Sixteen && !matches!(format, ImageFormat::Png | ImageFormat::Tiff) {
+        return Err(anyhow::anyhow!(
+            "{} can't be written with 16 bits per channel, use a png or tiff file",
+            path
+        ));
+    }
+    let alpha_format = matches!(
+        format,
+        ImageFormat::Png
+            | ImageFormat::Tiff
+            | ImageFormat::Tga
+            | ImageFormat::Bmp
+            | ImageFormat::Ico
+            | ImageFormat::Farbfeld
+    );
+    if alpha && !alpha_format {
+        return Err(anyhow::anyhow!(
+            "{} can't store an alpha channel, use a png or tiff file",
+            path
+        ));
+    }
+    Ok(())
+}
+
+/// warns that the render metadata (samples per pixel, render time...) will be lost when
+/// writing to `path`, only png files keep it
+pub(crate) fn warn_dropped_metadata(path: &str) {
+    if !matches!(ImageFormat::from_path(path), Ok(ImageFormat::Png)) {
+        eprintln!(
+            "warning: {} can't store the render metadata (samples per pixel, render time), use a png file to keep it",
+            path
+        );
+    }
+}
+
+fn save_png(img: &DynamicImage, path: &str, metadata: &[(&str, String)]) -> anyhow::Result<()> {
+    let (color, depth) = match img.color() {
+        ColorType::Rgb8 => (png::ColorType::RGB, png::BitDepth::Eight),
+        ColorType::Rgba8 => (png::ColorType::RGBA, png::BitDepth::Eight),
+        ColorType::Rgb16 => (png::ColorType::RGB, png::BitDepth::Sixteen),
+        ColorType::Rgba16 => (png::ColorType::RGBA, png::BitDepth::Sixteen),
+        color => return Err(anyhow::anyhow!("unsupported png color type {:?}", color)),
+    };
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, img.width(), img.height());
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    let mut writer = encoder.write_header()?;
+    for (key, text) in metadata {
+        // tEXt chunk: keyword, null separator, text
+        let chunk = [key.as_bytes(), &[0], text.as_bytes()].concat();
+        writer.write_chunk(*b"tEXt", &chunk)?;
+    }
+    if depth == png::BitDepth::Sixteen {
+        // png samples are big endian, the image buffer ones are native
+        let data: Vec<u8> = img
+            .as_bytes()
+            .chunks_exact(2)
+            .flat_map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]).to_be_bytes())
+            .collect();
+        writer.write_image_data(&data)?;
+    } else {
+        writer.write_image_data(img.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// `out.png` -> `out_<suffix>.png`, for the images written next to the output
+pub(crate) fn suffixed_file_path(output_file_path: &str, suffix: &str) -> String {
+    let path = Path::new(output_file_path);
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}_{}.{}", stem, suffix, extension.to_string_lossy()),
+        None => format!("{}_{}", stem, suffix),
+    };
+    path.with_file_name(file_name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    fn temp_path(file_name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("rray-output-{}-{}", std::process::id(), file_name))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn alpha_is_rejected_without_alpha_format() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::new(2, 2));
+        assert!(save_image(&img, &temp_path("alpha.jpg"), &[]).is_err());
+        assert!(save_image(&img, &temp_path("alpha.png"), &[]).is_ok());
+    }
+}
@@ -4,8 +4,6 @@ use bvh::bounding_hierarchy::BHShape;
 use bvh::bvh::BVH;
 use nalgebra::base::Scalar;
 use nalgebra::{Point3, Vector3};
-use rand::prelude::ThreadRng;
-use rand_distr::{Distribution, UnitBall};
 use std::cmp::Ordering;
 
 pub(crate) type RT = f32;
@@ -50,12 +48,17 @@ pub(crate) struct RayHit {
     /// when the ray hit
     pub t: RT,
     pub front_face: bool,
+    /// texture coordinates of the point, in [0, 1]
+    pub uv: [RT; 2],
+    /// bvh node index of the object hit
+    pub node_index: usize,
 }
 
 pub(crate) trait Hittable {
     fn hit(&self, ray: &Ray<RT>, t_min: RT, t_max: RT) -> Option<RayHit>;
 }
 
+#[derive(Debug)]
 pub(crate) struct Sphere {
     center: Point3<RT>,
     radius: RT,
@@ -108,6 +111,8 @@ impl Hittable for Sphere {
                         material: self.material.clone(),
                         t,
                         front_face,
+                        uv: sphere_uv(&outward_normal),
+                        node_index: self.node_index,
                     })
                 }
                 None => None,
@@ -118,6 +123,17 @@ impl Hittable for Sphere {
     }
 }
 
+/// texture coordinates of a point of the unit sphere: u goes around the y axis starting
+/// from -x, v goes from the bottom (y = -1) to the top
+fn sphere_uv(point: &Vector3<RT>) -> [RT; 2] {
+    let theta = (-point.y).clamp(-1., 1.).acos();
+    let phi = (-point.z).atan2(point.x) + std::f32::consts::PI;
+    [
+        phi / (2. * std::f32::consts::PI),
+        theta / std::f32::consts::PI,
+    ]
+}
+
 impl Bounded for Sphere {
     fn aabb(&self) -> AABB {
         let half_size = Vector3::new(self.radius, self.radius, self.radius);
@@ -137,6 +153,7 @@ impl BHShape for Sphere {
     }
 }
 
+#[derive(Debug)]
 pub(crate) enum Target {
     Sphere(Sphere),
 }
@@ -199,13 +216,14 @@ pub(crate) fn shoot_ray(
                     }
                 },
             );
-    match closest_hit {
-        Some(maybe_hit) => maybe_hit,
-        _ => None,
-    }
+    closest_hit.unwrap_or_default()
 }
 
-pub(crate) fn random_in_unit_sphere(thread_rng: &mut ThreadRng) -> Vector3<RT> {
-    let v: [RT; 3] = UnitBall.sample(thread_rng);
-    Vector3::new(v[0], v[1], v[2])
+/// maps a uniform sample of the unit cube to a point of the unit ball
+pub(crate) fn random_in_unit_sphere([u, v, w]: [RT; 3]) -> Vector3<RT> {
+    let z = 1. - 2. * u;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * std::f32::consts::PI as RT * v;
+    let radius = w.cbrt();
+    Vector3::new(r * phi.cos(), r * phi.sin(), z).scale(radius)
 }
@@ -0,0 +1,686 @@
+use crate::adaptive::allocate_pass;
+use crate::aov::{aov_file_path, aov_image, AovSample};
+use crate::camera::Camera;
+use crate::checkpoint::{fingerprint, Checkpoint, CheckpointHeader};
+use crate::cli::RConfig;
+use crate::color::RRgb;
+use crate::denoise::denoise;
+use crate::film::{Film, FilmTile};
+use crate::light::PunctualLight;
+use crate::material::{Material, Scatterer};
+use crate::output::{save_image, suffixed_file_path, BitDepth};
+use crate::ray::{shoot_ray, Ray, RayHit, Target, RT};
+use crate::sampler::Sampler;
+use crate::spectrum::{Radiance, SampledSpectrum, SampledWavelengths};
+use crate::tile::{tiles, Tile};
+use bvh::bvh::BVH;
+use image::{imageops, DynamicImage, ImageBuffer, RgbaImage};
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::ThreadPool;
+use std::f64::consts::PI;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Progressive rendering settings, the image is rendered in passes of `pass_spp` samples
+/// per pixel and the current estimate is written after each pass.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Progressive {
+    pub pass_spp: usize,
+    /// min duration between two snapshots, a snapshot is written after every pass if none
+    pub snapshot_interval: Option<Duration>,
+}
+
+/// Part of a render distributed across processes, shard `index` of `count` renders
+/// its own range of sample indices of every pixel.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Shard {
+    pub index: usize,
+    pub count: usize,
+}
+
+/// Subwindow of the image to render, in image pixels (y axis points downward).
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// How a cropped render is written.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum CropOutput {
+    /// an image of the size of the crop window
+    Cropped,
+    /// the crop window within a transparent full size image
+    Canvas,
+}
+
+impl FromStr for CropOutput {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cropped" => Ok(CropOutput::Cropped),
+            "canvas" => Ok(CropOutput::Canvas),
+            _ => Err(anyhow::anyhow!(
+                "invalid crop output {}, expected cropped or canvas",
+                s
+            )),
+        }
+    }
+}
+
+/// returns the light reflected towards `ray_hit` by a diffuse surface of color `albedo`,
+/// coming straight from the punctual lights
+///
+/// Punctual lights cannot be hit by scattered rays, they are only sampled here. Any surface
+/// between the light and the hit point casts a shadow, even a transparent one.
+fn direct_lighting<R: Radiance>(
+    ray_hit: &RayHit,
+    albedo: &RRgb,
+    world: &[Target],
+    bvh: &BVH,
+    lights: &[PunctualLight],
+    context: &R::Context,
+) -> R {
+    let mut irradiance = R::black();
+    for light in lights {
+        let (to_light, distance, scale) = match light.illuminate(&ray_hit.point) {
+            Some(illumination) => illumination,
+            None => continue,
+        };
+        let cos_theta = ray_hit.normal.dot(&to_light);
+        if cos_theta <= 0. {
+            continue;
+        }
+        let shadow_ray = Ray::new(ray_hit.point, to_light);
+        if shoot_ray(&shadow_ray, world, bvh, 0.01, distance - 0.01).is_some() {
+            continue;
+        }
+        irradiance = irradiance + R::from_light(light.emitter(), context) * (cos_theta * scale);
+    }
+    // a diffuse surface reflects 1 / π of the irradiance per unit solid angle
+    R::from_attenuation(albedo, context) * irradiance * (1. / PI as RT)
+}
+
+/// returns the light coming along `ray`, the first surface hit is recorded in `first_hit`
+///
+/// The light is traced in RGB, or at the wavelengths given by `context` in spectral mode.
+#[allow(clippy::too_many_arguments)]
+fn ray_color<R: Radiance>(
+    ray: &Ray<RT>,
+    world: &[Target],
+    bvh: &BVH,
+    lights: &[PunctualLight],
+    depth: usize,
+    bounce: usize,
+    sampler: &mut Sampler,
+    context: &mut R::Context,
+    first_hit: &mut Option<AovSample>,
+) -> R {
+    if depth == 0 {
+        return R::black();
+    }
+    let hit = shoot_ray(ray, world, bvh, 0.01, RT::INFINITY);
+    match hit {
+        Some(ray_hit) => {
+            if bounce == 0 {
+                *first_hit = Some(AovSample::new(&ray_hit));
+            }
+            let emitted = match &ray_hit.material {
+                Material::Lambertian(lambertian) if !lights.is_empty() => {
+                    R::from_emission(&ray_hit.material, &ray_hit, context)
+                        + direct_lighting(&ray_hit, &lambertian.albedo, world, bvh, lights, context)
+                }
+                _ => R::from_emission(&ray_hit.material, &ray_hit, context),
+            };
+            sampler.start_bounce(bounce);
+            if let Some((attenuation, scattered)) =
+                ray_hit
+                    .material
+                    .scatter(ray, &ray_hit, sampler, R::wavelengths(context))
+            {
+                emitted
+                    + R::from_attenuation(&attenuation, context)
+                        * ray_color::<R>(
+                            &scattered,
+                            world,
+                            bvh,
+                            lights,
+                            depth - 1,
+                            bounce + 1,
+                            sampler,
+                            context,
+                            first_hit,
+                        )
+            } else {
+                emitted
+            }
+        }
+        None => R::black(),
+    }
+}
+
+pub(crate) struct Renderer<'a> {
+    world: &'a [Target],
+    bvh: &'a BVH,
+    camera: &'a Camera,
+    config: &'a RConfig,
+    thread_pool: &'a ThreadPool,
+    /// rendered region of the image, in film coordinates (y axis points upward)
+    window: Tile,
+    /// film regions in rendering order, relative to the window
+    tiles: Vec<Tile>,
+    /// where to save the render state, none if the render cannot be resumed
+    checkpoint_path: Option<String>,
+    /// render state to start from
+    resume: Option<Checkpoint>,
+}
+
+impl<'a> Renderer<'a> {
+    pub(crate) fn new(
+        world: &'a [Target],
+        bvh: &'a BVH,
+        camera: &'a Camera,
+        config: &'a RConfig,
+        thread_pool: &'a ThreadPool,
+    ) -> Self {
+        let image_height = config.get_image_height();
+        let window = match &config.crop {
+            Some(crop) => Tile {
+                x: crop.x,
+                y: image_height - crop.y - crop.height,
+                width: crop.width,
+                height: crop.height,
+            },
+            None => Tile {
+                x: 0,
+                y: 0,
+                width: config.image_width as u32,
+                height: image_height,
+            },
+        };
+        let tiles = tiles(
+            window.width,
+            window.height,
+            config.tile_size,
+            config.tile_order,
+        );
+        Renderer {
+            world,
+            bvh,
+            camera,
+            config,
+            thread_pool,
+            window,
+            tiles,
+            checkpoint_path: None,
+            resume: None,
+        }
+    }
+
+    pub(crate) fn with_checkpoint(self, checkpoint_path: Option<String>) -> Self {
+        Renderer {
+            checkpoint_path,
+            ..self
+        }
+    }
+
+    pub(crate) fn with_resume(self, resume: Option<Checkpoint>) -> Self {
+        Renderer { resume, ..self }
+    }
+
+    /// identifies the render, a checkpoint can only be resumed by the same render
+    fn checkpoint_header(&self, pass_index: u64) -> CheckpointHeader {
+        let config = self.config;
+        let config_description = format!(
+            "{} {} {:?} {} {:?} {:?} {:?} {:?} {} {:?} {}",
+            config.image_width,
+            config.get_image_height(),
+            self.window,
+            config.max_depth,
+            config.sampler,
+            config.filter,
+            config.aovs,
+            self.camera,
+            config.denoise,
+            config.max_sample_luminance,
+            config.spectral
+        );
+        CheckpointHeader {
+            seed: config.seed,
+            config_hash: fingerprint(&config_description),
+            scene_hash: fingerprint(&format!("{:?} {:?}", self.world, self.config.lights)),
+            pass_index,
+            shard_index: config.shard.map_or(0, |shard| shard.index as u64),
+            shard_count: config.shard.map_or(1, |shard| shard.count as u64),
+            sample_per_pixel: config.sample_per_pixel as u64,
+            sampler_strata: config.sampler.strata(config.get_max_sample_per_pixel()) as u64,
+        }
+    }
+
+    /// returns the film to start from, checking that the checkpoint matches this render
+    fn initial_film(&mut self) -> anyhow::Result<(Film, u64)> {
+        match self.resume.take() {
+            None => Ok((
+                Film::new(
+                    self.window.width,
+                    self.window.height,
+                    self.config.aovs.clone(),
+                    self.config.denoise,
+                ),
+                0,
+            )),
+            Some(checkpoint) => {
+                let header = checkpoint.header;
+                let expected = self.checkpoint_header(header.pass_index);
+                if header.seed != expected.seed {
+                    Err(anyhow::anyhow!(
+                        "the checkpoint was rendered with seed {}, use --seed {}",
+                        header.seed,
+                        header.seed
+                    ))
+                } else if header.scene_hash != expected.scene_hash {
+                    Err(anyhow::anyhow!(
+                        "the checkpoint was rendered from another scene"
+                    ))
+                } else if (header.shard_index, header.shard_count)
+                    != (expected.shard_index, expected.shard_count)
+                {
+                    Err(anyhow::anyhow!(
+                        "the checkpoint was rendered as shard {}/{}",
+                        header.shard_index,
+                        header.shard_count
+                    ))
+                } else if self.config.shard.is_some()
+                    && header.sample_per_pixel != expected.sample_per_pixel
+                {
+                    // the sample indices of a shard depend on the samples per pixel
+                    Err(anyhow::anyhow!(
+                        "the shard was rendered with {} samples per pixel, use -s {}",
+                        header.sample_per_pixel,
+                        header.sample_per_pixel
+                    ))
+                } else if header.sampler_strata != expected.sampler_strata {
+                    // resumed samples would fall in other strata than the ones already rendered
+                    Err(anyhow::anyhow!(
+                        "the checkpoint samples are stratified over {} samples per pixel, use the same (maximum) samples per pixel",
+                        header.sampler_strata
+                    ))
+                } else if header.config_hash != expected.config_hash {
+                    Err(anyhow::anyhow!(
+                        "the checkpoint was rendered with other settings (size, crop, camera, max depth, sampler, filter, aovs or denoising)"
+                    ))
+                } else {
+                    Ok((checkpoint.film, header.pass_index))
+                }
+            }
+        }
+    }
+
+    /// sample indices rendered by this process, all of them unless rendering a shard
+    fn sample_range(&self) -> Range<usize> {
+        let sample_per_pixel = self.config.sample_per_pixel;
+        match self.config.shard {
+            Some(shard) => {
+                sample_per_pixel * shard.index / shard.count
+                    ..sample_per_pixel * (shard.index + 1) / shard.count
+            }
+            None => 0..sample_per_pixel,
+        }
+    }
+
+    /// traces `sample_count` samples of pixel (x, y) of the film, starting at sample index
+    /// `first_sample`, and splats them into `film_tile`
+    fn pixel_samples(
+        &self,
+        (x, y): (u32, u32),
+        first_sample: usize,
+        sample_count: usize,
+        film_tile: &mut FilmTile,
+    ) {
+        let config = self.config;
+        let image_height = config.get_image_height();
+        let mut sampler = Sampler::new(
+            config.sampler,
+            config.get_max_sample_per_pixel(),
+            config.seed,
+        );
+        // image coordinates, the film only covers the crop window
+        let (image_x, image_y) = (self.window.x + x, self.window.y + y);
+        for sample_index in first_sample..first_sample + sample_count {
+            sampler.start_pixel_sample(image_x, image_y, sample_index);
+            let [du, dv] = sampler.get_2d();
+            let lens = sampler.get_2d();
+            let u = (image_x as RT + du) / config.image_width as RT;
+            let v = (image_y as RT + dv) / image_height as RT;
+            let ray = self.camera.get_ray(u, v, lens);
+            let mut first_hit = None;
+            let color = if config.spectral {
+                let mut wavelengths = SampledWavelengths::sample_uniform(sampler.get_1d());
+                let spectrum: SampledSpectrum = ray_color(
+                    &ray,
+                    self.world,
+                    self.bvh,
+                    &config.lights,
+                    config.max_depth,
+                    0,
+                    &mut sampler,
+                    &mut wavelengths,
+                    &mut first_hit,
+                );
+                spectrum.to_rgb(&wavelengths)
+            } else {
+                ray_color(
+                    &ray,
+                    self.world,
+                    self.bvh,
+                    &config.lights,
+                    config.max_depth,
+                    0,
+                    &mut sampler,
+                    &mut (),
+                    &mut first_hit,
+                )
+            };
+            if let Some(first_hit) = &mut first_hit {
+                first_hit.depth = self.camera.depth(&first_hit.position);
+            }
+            let alpha = match &first_hit {
+                Some(first_hit) if !first_hit.holdout => 1.,
+                _ => 0.,
+            };
+            film_tile.add_sample(
+                (x, y),
+                [x as RT + du, y as RT + dv],
+                &color,
+                alpha,
+                first_hit.as_ref(),
+                &config.filter,
+            );
+        }
+    }
+
+    /// adds `samples[p]` samples to each pixel p of the film
+    ///
+    /// Workers take the tiles in order and merge each rendered tile into the film.
+    fn render_pass(&self, film: &mut Film, samples: &[usize], progress_bar: &ProgressBar) {
+        let (width, height) = (film.width(), film.height());
+        let first_sample = self.sample_range().start;
+        let first_samples: Vec<usize> = film
+            .pixels()
+            .iter()
+            .map(|p| first_sample + p.sample_count)
+            .collect();
+        // pixels reached by the samples of a tile, outside of the tile
+        let margin = (self.config.filter.radius - 0.5).ceil().max(0.) as u32;
+        let capture_aovs = !film.aovs().is_empty();
+        let film = Mutex::new(film);
+        let next_tile = AtomicUsize::new(0);
+        let rendered_tiles = AtomicUsize::new(0);
+        let render_tiles = || {
+            while let Some(tile) = self.tiles.get(next_tile.fetch_add(1, Ordering::Relaxed)) {
+                let mut tile_samples = 0;
+                let mut film_tile = FilmTile::new(
+                    tile.expand(margin, width, height),
+                    capture_aovs,
+                    self.config.max_sample_luminance,
+                );
+                for (x, y) in tile.pixels() {
+                    let p = (y * width + x) as usize;
+                    if samples[p] > 0 {
+                        self.pixel_samples((x, y), first_samples[p], samples[p], &mut film_tile);
+                        tile_samples += samples[p];
+                    }
+                }
+                film.lock().unwrap().merge_tile(&film_tile);
+                let rendered_tiles = rendered_tiles.fetch_add(1, Ordering::Relaxed) + 1;
+                progress_bar.set_message(&format!("tile {}/{}", rendered_tiles, self.tiles.len()));
+                progress_bar.inc(tile_samples as u64);
+            }
+        };
+        self.thread_pool.scope(|scope| {
+            for _ in 0..self.thread_pool.current_num_threads() {
+                scope.spawn(|_| render_tiles());
+            }
+        });
+    }
+
+    /// converts the film to the output image, placing a cropped render on a canvas if requested
+    fn output_image(&self, img: DynamicImage) -> DynamicImage {
+        let config = self.config;
+        match (&config.crop, config.crop_output) {
+            (Some(crop), CropOutput::Canvas) => {
+                // pixels outside of the crop window are fully transparent
+                let (width, height) = (config.image_width as u32, config.get_image_height());
+                match config.bit_depth {
+                    BitDepth::Eight => {
+                        let mut canvas = RgbaImage::new(width, height);
+                        imageops::replace(&mut canvas, &img.to_rgba8(), crop.x, crop.y);
+                        DynamicImage::ImageRgba8(canvas)
+                    }
+                    BitDepth::Sixteen => {
+                        let mut canvas = ImageBuffer::new(width, height);
+                        imageops::replace(&mut canvas, &img.to_rgba16(), crop.x, crop.y);
+                        DynamicImage::ImageRgba16(canvas)
+                    }
+                }
+            }
+            _ => img,
+        }
+    }
+
+    /// writes the image, or the accumulated samples when rendering a shard
+    fn write_output(
+        &self,
+        film: &Film,
+        output_file_path: &str,
+        metadata: &[(&str, String)],
+        pass_index: u64,
+    ) -> anyhow::Result<()> {
+        match self.config.shard {
+            Some(_) => {
+                Checkpoint::save(output_file_path, &self.checkpoint_header(pass_index), film)
+            }
+            None => {
+                let colors = if self.config.denoise {
+                    self.thread_pool.install(|| denoise(film))
+                } else {
+                    film.colors()
+                };
+                let img = film.to_color_image(&colors, self.config.alpha, self.config.bit_depth);
+                save_image(&self.output_image(img), output_file_path, metadata)?;
+                for &aov in film.aov_kinds() {
+                    let (img, range) = aov_image(film, aov, self.config.bit_depth);
+                    let mut metadata = metadata.to_vec();
+                    if let Some(range) = range {
+                        metadata.push(("Range", range));
+                    }
+                    save_image(
+                        &self.output_image(img),
+                        &aov_file_path(output_file_path, aov),
+                        &metadata,
+                    )?;
+                }
+                if self.config.debug_samples {
+                    save_image(
+                        &self.output_image(DynamicImage::ImageRgb8(film.to_debug_image())),
+                        &suffixed_file_path(output_file_path, "debug"),
+                        metadata,
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// renders the image and writes it to `output_file_path`
+    pub(crate) fn render(mut self, output_file_path: &str) -> anyhow::Result<()> {
+        let config = self.config;
+        let start = Instant::now();
+        let (mut film, mut pass_index) = self.initial_film()?;
+        let pixel_count = film.pixels().len();
+        let sample_per_pixel = self.sample_range().len();
+        let sample_budget = sample_per_pixel * pixel_count;
+
+        let progress_bar = match config.time_limit {
+            // progress is measured in time, the number of samples is unknown
+            Some(time_limit) => ProgressBar::new(time_limit.as_millis() as u64)
+                .with_style(ProgressStyle::default_bar().template("{bar} [{elapsed}] {msg}")),
+            None => {
+                let progress_bar = ProgressBar::new(sample_budget as u64).with_style(
+                    ProgressStyle::default_bar().template("{bar} [{elapsed}] ETA {eta} {msg}"),
+                );
+                progress_bar.set_draw_delta((sample_budget / 1000) as u64); // limit progress_bar redraw
+                progress_bar.set_position(film.sample_count().min(sample_budget) as u64);
+                progress_bar
+            }
+        };
+        let sample_progress_bar = match config.time_limit {
+            Some(_) => ProgressBar::hidden(),
+            None => progress_bar.clone(),
+        };
+
+        let metadata = |film: &Film| {
+            vec![
+                ("Software", String::from(crate_name!())),
+                (
+                    "Samples per pixel",
+                    format!("{:.2}", film.sample_count() as f64 / pixel_count as f64),
+                ),
+                (
+                    "Render time",
+                    format!("{:.3}s", start.elapsed().as_secs_f64()),
+                ),
+            ]
+        };
+        let mut last_snapshot = Instant::now();
+        let mut last_checkpoint = Instant::now();
+        let renderer = &self;
+        let mut end_of_pass = |film: &Film| -> anyhow::Result<()> {
+            pass_index += 1;
+            if let Some(checkpoint_path) = &renderer.checkpoint_path {
+                if last_checkpoint.elapsed() >= config.checkpoint_interval {
+                    let header = renderer.checkpoint_header(pass_index);
+                    Checkpoint::save(checkpoint_path, &header, film)?;
+                    last_checkpoint = Instant::now();
+                }
+            }
+            if config.time_limit.is_some() {
+                progress_bar.set_position(start.elapsed().as_millis() as u64);
+                progress_bar.set_message(&format!(
+                    "{:.1} spp",
+                    film.sample_count() as f64 / pixel_count as f64
+                ));
+            }
+            if let Some(progressive) = &config.progressive {
+                let due = match progressive.snapshot_interval {
+                    Some(interval) => last_snapshot.elapsed() >= interval,
+                    None => true,
+                };
+                if due {
+                    renderer.write_output(film, output_file_path, &metadata(film), pass_index)?;
+                    last_snapshot = Instant::now();
+                }
+            }
+            Ok(())
+        };
+        // a pass is only started if it is expected to end within the time limit
+        let has_time_for = |pass_duration: Duration| match config.time_limit {
+            Some(time_limit) => start.elapsed() + pass_duration <= time_limit,
+            None => true,
+        };
+
+        let pass_spp = match &config.progressive {
+            Some(progressive) => progressive.pass_spp,
+            // small passes, so that the time limit is not overshot and checkpoints are regular
+            None if config.time_limit.is_some() || self.checkpoint_path.is_some() => 1,
+            None => sample_per_pixel,
+        };
+        let mut pass_duration = Duration::from_secs(0);
+        match &config.adaptive {
+            None => {
+                // resumed pixels all have the same number of samples
+                let mut rendered_spp = film
+                    .pixels()
+                    .iter()
+                    .map(|p| p.sample_count)
+                    .min()
+                    .unwrap_or(0);
+                let resumed_spp = rendered_spp;
+                // at least one pass, even if the time limit is too short
+                while rendered_spp < sample_per_pixel
+                    && (rendered_spp == resumed_spp || has_time_for(pass_duration))
+                {
+                    let pass_start = Instant::now();
+                    let spp = pass_spp.min(sample_per_pixel - rendered_spp);
+                    self.render_pass(&mut film, &vec![spp; pixel_count], &sample_progress_bar);
+                    rendered_spp += spp;
+                    pass_duration = pass_start.elapsed();
+                    end_of_pass(&film)?;
+                }
+            }
+            Some(adaptive) => {
+                // the first pass estimates the variance of every pixel
+                if film.sample_count() == 0 {
+                    let pass_start = Instant::now();
+                    let samples = vec![adaptive.min_spp; pixel_count];
+                    self.render_pass(&mut film, &samples, &sample_progress_bar);
+                    pass_duration = pass_start.elapsed();
+                    end_of_pass(&film)?;
+                }
+                while has_time_for(pass_duration) {
+                    let pass_start = Instant::now();
+                    let remaining_budget = sample_budget.saturating_sub(film.sample_count());
+                    let pass_budget = match config.time_limit {
+                        Some(_) => pixel_count * pass_spp,
+                        None => sample_budget / 8,
+                    };
+                    let pass_budget = remaining_budget.min(pass_budget).max(1);
+                    let samples = allocate_pass(&film, adaptive, pass_budget);
+                    if remaining_budget == 0 || samples.iter().all(|&s| s == 0) {
+                        break;
+                    }
+                    self.render_pass(&mut film, &samples, &sample_progress_bar);
+                    pass_duration = pass_start.elapsed();
+                    end_of_pass(&film)?;
+                }
+            }
+        }
+        progress_bar.finish();
+        if config.time_limit.is_some() {
+            eprintln!(
+                "rendered {:.2} spp in {:.1?}",
+                film.sample_count() as f64 / pixel_count as f64,
+                start.elapsed()
+            );
+        }
+        print_sample_summary(&film);
+        if let Some(checkpoint_path) = &self.checkpoint_path {
+            Checkpoint::save(checkpoint_path, &self.checkpoint_header(pass_index), &film)?;
+        }
+        self.write_output(&film, output_file_path, &metadata(&film), pass_index)
+    }
+}
+
+/// warns about the samples that were discarded or clamped
+pub(crate) fn print_sample_summary(film: &Film) {
+    let (invalid_samples, invalid_pixels) = film.invalid_sample_count();
+    if invalid_samples > 0 {
+        eprintln!(
+            "warning: {} NaN or infinite samples discarded in {} pixels, see --debug-samples",
+            invalid_samples, invalid_pixels
+        );
+    }
+    let (clamped_samples, clamped_pixels) = film.clamped_sample_count();
+    if clamped_samples > 0 {
+        eprintln!(
+            "warning: {} samples clamped to the maximum luminance in {} pixels",
+            clamped_samples, clamped_pixels
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use crate::ray::RT;
+use std::str::FromStr;
+
+/// Sample dimensions used by a camera sample, bounce decisions start right after.
+///
+/// 0-1: position within the pixel, 2-3: position on the lens, 4: hero wavelength (spectral
+/// mode), 5: time (reserved).
+const CAMERA_DIMENSIONS: usize = 6;
+
+/// Sample dimensions reserved for each bounce, enough for any material.
+const BOUNCE_DIMENSIONS: usize = 3;
+
+/// Bases of the Halton sequence, one per dimension.
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum SamplerKind {
+    /// uncorrelated uniform random samples
+    Independent,
+    /// jittered samples, one per stratum of each dimension
+    Stratified,
+    /// Halton sequence with randomly permuted digits
+    Halton,
+    /// Owen-scrambled Sobol sequence, padded by shuffling 1D/2D sequences
+    Sobol,
+}
+
+impl SamplerKind {
+    /// strata of each dimension for the given (maximum) samples per pixel, 0 if not stratified
+    ///
+    /// Samples only cover their strata together, the samples of renders with other strata do not mix.
+    pub(crate) fn strata(&self, sample_per_pixel: usize) -> usize {
+        match self {
+            SamplerKind::Stratified => sample_per_pixel,
+            SamplerKind::Independent | SamplerKind::Halton | SamplerKind::Sobol => 0,
+        }
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(anyhow::anyhow!(
+                "invalid sampler {}, expected independent, stratified, halton or sobol",
+                s
+            )),
+        }
+    }
+}
+
+/// Provides the random numbers of a pixel sample.
+///
+/// Samples are a deterministic function of the seed, the pixel, the sample index and the
+/// dimension: the same pixel sample always traces the same path, whatever the thread.
+#[derive(Clone, Debug)]
+pub(crate) struct Sampler {
+    kind: SamplerKind,
+    sample_per_pixel: usize,
+    seed: u64,
+    pixel_hash: u64,
+    sample_index: usize,
+    dimension: usize,
+}
+
+impl Sampler {
+    pub(crate) fn new(kind: SamplerKind, sample_per_pixel: usize, seed: u64) -> Self {
+        Sampler {
+            kind,
+            sample_per_pixel,
+            seed,
+            pixel_hash: 0,
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+
+    pub(crate) fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: usize) {
+        self.pixel_hash = hash(&[self.seed, x as u64, y as u64]);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    /// moves to the dimensions of the given bounce, 0 being the camera ray hit
+    pub(crate) fn start_bounce(&mut self, bounce: usize) {
+        self.dimension = CAMERA_DIMENSIONS + bounce * BOUNCE_DIMENSIONS;
+    }
+
+    pub(crate) fn get_1d(&mut self) -> RT {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        let dimension_hash = hash(&[self.pixel_hash, dimension as u64]);
+        match self.kind {
+            SamplerKind::Independent => to_unit(hash(&[dimension_hash, self.sample_index as u64])),
+            SamplerKind::Stratified => {
+                let strata = self.sample_per_pixel as u32;
+                let stratum = permute(self.sample_index as u32 % strata, strata, dimension_hash);
+                let jitter = to_unit(hash(&[dimension_hash, self.sample_index as u64]));
+                (stratum as RT + jitter) / strata as RT
+            }
+            SamplerKind::Halton => halton(dimension, self.sample_index as u64, dimension_hash),
+            SamplerKind::Sobol => {
+                let index = nested_uniform_scramble(self.sample_index as u32, dimension_hash);
+                let x = nested_uniform_scramble(sobol(index, 0), hash(&[dimension_hash, 0]));
+                to_unit_u32(x)
+            }
+        }
+    }
+
+    pub(crate) fn get_2d(&mut self) -> [RT; 2] {
+        match self.kind {
+            SamplerKind::Independent | SamplerKind::Halton => [self.get_1d(), self.get_1d()],
+            SamplerKind::Stratified => {
+                let dimension = self.dimension;
+                self.dimension += 2;
+                let dimension_hash = hash(&[self.pixel_hash, dimension as u64]);
+                let nx = (self.sample_per_pixel as f64).sqrt().ceil() as u32;
+                let ny = (self.sample_per_pixel as u32).div_ceil(nx);
+                let strata = nx * ny;
+                let stratum = permute(self.sample_index as u32 % strata, strata, dimension_hash);
+                let jitter_x = to_unit(hash(&[dimension_hash, self.sample_index as u64, 0]));
+                let jitter_y = to_unit(hash(&[dimension_hash, self.sample_index as u64, 1]));
+                [
+                    ((stratum % nx) as RT + jitter_x) / nx as RT,
+                    ((stratum / nx) as RT + jitter_y) / ny as RT,
+                ]
+            }
+            SamplerKind::Sobol => {
+                let dimension = self.dimension;
+                self.dimension += 2;
+                let dimension_hash = hash(&[self.pixel_hash, dimension as u64]);
+                // both dimensions share the index shuffle, so that the pair stays a (0, 2) sequence
+                let index = nested_uniform_scramble(self.sample_index as u32, dimension_hash);
+                let x = nested_uniform_scramble(sobol(index, 0), hash(&[dimension_hash, 0]));
+                let y = nested_uniform_scramble(sobol(index, 1), hash(&[dimension_hash, 1]));
+                [to_unit_u32(x), to_unit_u32(y)]
+            }
+        }
+    }
+}
+
+/// mixes the given values into a 64 bits hash (splitmix64 finalizer)
+pub(crate) fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |h, &v| {
+        let mut z = (h ^ v).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    })
+}
+
+/// maps a hash to [0, 1)
+fn to_unit(h: u64) -> RT {
+    to_unit_u32((h >> 32) as u32)
+}
+
+/// maps a 32 bits fixed point number to [0, 1)
+fn to_unit_u32(x: u32) -> RT {
+    (x >> 8) as RT * (1. / (1u32 << 24) as RT)
+}
+
+/// returns the image of `i` by a random permutation of [0, n) (Kensler, Correlated Multi-Jittered Sampling)
+fn permute(mut i: u32, n: u32, seed: u64) -> u32 {
+    let p = seed as u32;
+    let mut w = n.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % n
+}
+
+/// radical inverse of `index` in the prime base of `dimension`, with randomly permuted digits
+///
+/// Each digit position gets its own permutation seeded by `permutation_hash`, this
+/// decorrelates pixels and breaks the correlation between high dimensions.
+fn halton(dimension: usize, index: u64, permutation_hash: u64) -> RT {
+    if dimension >= PRIMES.len() {
+        // no more bases, fall back to random samples
+        return to_unit(hash(&[permutation_hash, index]));
+    }
+    let base = PRIMES[dimension];
+    let inverse_base = 1. / base as f64;
+    let mut inverse_base_n = inverse_base;
+    let mut radical_inverse = 0f64;
+    let mut index = index;
+    let mut digit_position = 0u64;
+    // permuted trailing zeros still contribute, stop once below the output precision
+    while inverse_base_n > 1e-9 {
+        let digit = (index % base as u64) as u32;
+        let digit_hash = hash(&[permutation_hash, digit_position]);
+        radical_inverse += permute(digit, base, digit_hash) as f64 * inverse_base_n;
+        inverse_base_n *= inverse_base;
+        index /= base as u64;
+        digit_position += 1;
+    }
+    (radical_inverse as RT).min(1. - RT::EPSILON)
+}
+
+/// first two dimensions of the Sobol sequence, as 32 bits fixed point numbers
+fn sobol(index: u32, dimension: usize) -> u32 {
+    match dimension {
+        0 => index.reverse_bits(),
+        _ => {
+            // direction numbers of the second dimension: v_1 = 1 << 31, v_i = v_(i-1) ^ (v_(i-1) >> 1)
+            let mut result = 0u32;
+            let mut v = 1u32 << 31;
+            let mut index = index;
+            while index != 0 {
+                if index & 1 != 0 {
+                    result ^= v;
+                }
+                index >>= 1;
+                v ^= v >> 1;
+            }
+            result
+        }
+    }
+}
+
+/// Owen scrambling of a 32 bits fixed point number (Burley, Practical Hash-based Owen Scrambling)
+fn nested_uniform_scramble(x: u32, seed: u64) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed as u32).reverse_bits()
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    /// first dimensions of a pixel sample, 1D and 2D samples interleaved
+    fn pixel_sample(sampler: &mut Sampler, (x, y): (u32, u32), sample_index: usize) -> Vec<RT> {
+        sampler.start_pixel_sample(x, y, sample_index);
+        let mut values = vec![];
+        for bounce in 0..12 {
+            sampler.start_bounce(bounce);
+            values.extend(&sampler.get_2d());
+            values.push(sampler.get_1d());
+        }
+        values
+    }
+
+    #[test]
+    fn samples_are_in_unit_interval() {
+        for &kind in KINDS.iter() {
+            let mut sampler = Sampler::new(kind, 16, 7);
+            for sample_index in 0..64 {
+                for value in pixel_sample(&mut sampler, (3, 5), sample_index) {
+                    assert!((0. ..1.).contains(&value), "{:?} sample {}", kind, value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_only_depend_on_seed_pixel_and_index() {
+        for &kind in KINDS.iter() {
+            let mut sampler = Sampler::new(kind, 16, 7);
+            let first = pixel_sample(&mut sampler, (3, 5), 9);
+            pixel_sample(&mut sampler, (4, 5), 2);
+            assert_eq!(first, pixel_sample(&mut sampler, (3, 5), 9));
+            assert_eq!(
+                first,
+                pixel_sample(&mut Sampler::new(kind, 16, 7), (3, 5), 9)
+            );
+            assert_ne!(
+                first,
+                pixel_sample(&mut Sampler::new(kind, 16, 8), (3, 5), 9)
+            );
+        }
+    }
+
+    #[test]
+    fn stratified_samples_fill_every_stratum_once() {
+        let mut sampler = Sampler::new(SamplerKind::Stratified, 16, 7);
+        let mut strata_1d = [0; 16];
+        let mut strata_2d = [0; 16];
+        for sample_index in 0..16 {
+            sampler.start_pixel_sample(3, 5, sample_index);
+            let [x, y] = sampler.get_2d();
+            strata_2d[(y * 4.) as usize * 4 + (x * 4.) as usize] += 1;
+            strata_1d[(sampler.get_1d() * 16.) as usize] += 1;
+        }
+        assert_eq!(strata_1d, [1; 16]);
+        assert_eq!(strata_2d, [1; 16]);
+    }
+
+    #[test]
+    fn sobol_pairs_fill_every_stratum_once() {
+        let mut sampler = Sampler::new(SamplerKind::Sobol, 16, 7);
+        let mut strata = [0; 16];
+        for sample_index in 0..16 {
+            sampler.start_pixel_sample(3, 5, sample_index);
+            let [x, y] = sampler.get_2d();
+            strata[(y * 4.) as usize * 4 + (x * 4.) as usize] += 1;
+        }
+        assert_eq!(strata, [1; 16]);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::color::RRgb;
+use crate::material::{Dieletric, Emission, Lambertian, Light, Material, Metal};
+use crate::ray::{Sphere, Target, RT};
+use crate::spectrum::Spectrum;
+use crate::texture::Texture;
+use nalgebra::Point3;
+use rand::distributions::Uniform;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// returns the default scene: a light above a grid of random spheres
+///
+/// The same seed always gives the same scene. A holdout ground masks the bottom of the
+/// spheres without appearing in the image. The light emits `light_spectrum`, white by default,
+/// with a luminance of 1000 cd/m² unless `light_emission` is given, modulated by `light_texture`
+/// and only outward unless `light_two_sided`. Glass spheres are made of `glass`.
+pub(crate) fn random_spheres(
+    seed: u64,
+    holdout_ground: bool,
+    light_spectrum: Option<Spectrum>,
+    light_emission: Option<Emission>,
+    light_texture: Option<Texture>,
+    light_two_sided: bool,
+    glass: Dieletric,
+) -> Vec<Target> {
+    let material_ground = Lambertian {
+        albedo: RRgb::new(0.8, 0.8, 0.),
+    };
+    let light_radius: RT = 10.;
+    let material_light = Light::with_emission(
+        light_spectrum,
+        light_emission.unwrap_or(Emission::Luminance(1000.)),
+        4. * std::f64::consts::PI * (light_radius as f64).powi(2),
+    )
+    .with_texture(light_texture)
+    .with_two_sided(light_two_sided);
+    let material_metal = Metal {
+        albedo: RRgb::new(0.8, 0.8, 0.8),
+    };
+
+    let mut index = 0;
+
+    let ground = Target::Sphere(Sphere::new(
+        Point3::new(0.0, -100.5, -1.0),
+        100.0,
+        if holdout_ground {
+            Material::Holdout
+        } else {
+            Material::Lambertian(material_ground)
+        },
+        index,
+    ));
+
+    index += 1;
+    let sun = Target::Sphere(Sphere::new(
+        Point3::new(0.0, 20.0, -10.0),
+        light_radius,
+        Material::Light(material_light),
+        index,
+    ));
+
+    let mut world: Vec<Target> = vec![ground, sun];
+    let mut rng = StdRng::seed_from_u64(seed);
+    let side = Uniform::new(0., 1.);
+    for dx in -10..=10 {
+        for dz in -10..=0 {
+            let rdm = rng.sample(side);
+            let material: Material = if rdm < 0.80 {
+                let r = rng.sample(side);
+                let g = rng.sample(side);
+                let b = rng.sample(side);
+                Material::Lambertian(Lambertian {
+                    albedo: RRgb::new(r, g, b),
+                })
+            } else if rdm < 0.90 {
+                Material::Metal(material_metal.clone())
+            } else {
+                Material::Dieletric(glass.clone())
+            };
+            index += 1;
+            world.push(Target::Sphere(Sphere::new(
+                Point3::new(0.0 + dx as RT, 0.0, 0.0 + dz as RT),
+                (rdm * rdm) as RT,
+                material.clone(),
+                index,
+            )))
+        }
+    }
+    world
+}
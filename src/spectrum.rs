@@ -0,0 +1,497 @@
+use crate::color::RRgb;
+use crate::material::{Emitter, Light};
+use crate::ray::{RayHit, RT};
+use std::ops;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Wavelengths traced together by a path, the first one is the hero wavelength.
+pub(crate) const WAVELENGTH_COUNT: usize = 4;
+/// Sampled range of wavelengths, in nanometers.
+const LAMBDA_MIN: f64 = 360.;
+const LAMBDA_MAX: f64 = 830.;
+
+/// Wavelengths of a path and their probability densities.
+#[derive(Clone, Debug)]
+pub(crate) struct SampledWavelengths {
+    lambda: [f64; WAVELENGTH_COUNT],
+    pdf: [f64; WAVELENGTH_COUNT],
+}
+
+impl SampledWavelengths {
+    /// samples the hero wavelength uniformly from `u`, the others are evenly spaced after it
+    /// (Wilkie et al., Hero Wavelength Spectral Sampling)
+    pub(crate) fn sample_uniform(u: RT) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = LAMBDA_MIN + u as f64 * range;
+        let lambda = std::array::from_fn(|i| {
+            let lambda = hero + i as f64 * range / WAVELENGTH_COUNT as f64;
+            if lambda > LAMBDA_MAX {
+                lambda - range
+            } else {
+                lambda
+            }
+        });
+        SampledWavelengths {
+            lambda,
+            pdf: [1. / range; WAVELENGTH_COUNT],
+        }
+    }
+
+    pub(crate) fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    /// keeps only the hero wavelength, once the path depends on the wavelength
+    pub(crate) fn terminate_secondary(&mut self) {
+        if self.pdf[1] == 0. {
+            return;
+        }
+        // the hero wavelength now stands for all of them
+        self.pdf[0] /= WAVELENGTH_COUNT as f64;
+        for pdf in self.pdf.iter_mut().skip(1) {
+            *pdf = 0.;
+        }
+    }
+}
+
+/// Values of a spectrum at the wavelengths of a path.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SampledSpectrum([f64; WAVELENGTH_COUNT]);
+
+impl ops::Add<SampledSpectrum> for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn add(self, rhs: SampledSpectrum) -> Self::Output {
+        SampledSpectrum(std::array::from_fn(|i| self.0[i] + rhs.0[i]))
+    }
+}
+
+impl ops::Mul<SampledSpectrum> for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn mul(self, rhs: SampledSpectrum) -> Self::Output {
+        SampledSpectrum(std::array::from_fn(|i| self.0[i] * rhs.0[i]))
+    }
+}
+
+impl ops::Mul<RT> for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn mul(self, rhs: RT) -> Self::Output {
+        SampledSpectrum(self.0.map(|v| v * rhs as f64))
+    }
+}
+
+impl SampledSpectrum {
+    fn from_fn<F: Fn(f64) -> f64>(wavelengths: &SampledWavelengths, f: F) -> Self {
+        SampledSpectrum(wavelengths.lambda.map(f))
+    }
+
+    /// upsamples an RGB reflectance in [0, 1] (Smits, An RGB to Spectrum Conversion for Reflectances)
+    pub(crate) fn reflectance(rgb: &RRgb, wavelengths: &SampledWavelengths) -> Self {
+        let [r, g, b] = rgb.to_array();
+        // white, then the secondary color, then the primary color
+        let terms = if r <= g && r <= b {
+            if g <= b {
+                [
+                    (r, &SMITS_WHITE),
+                    (g - r, &SMITS_CYAN),
+                    (b - g, &SMITS_BLUE),
+                ]
+            } else {
+                [
+                    (r, &SMITS_WHITE),
+                    (b - r, &SMITS_CYAN),
+                    (g - b, &SMITS_GREEN),
+                ]
+            }
+        } else if g <= r && g <= b {
+            if r <= b {
+                [
+                    (g, &SMITS_WHITE),
+                    (r - g, &SMITS_MAGENTA),
+                    (b - r, &SMITS_BLUE),
+                ]
+            } else {
+                [
+                    (g, &SMITS_WHITE),
+                    (b - g, &SMITS_MAGENTA),
+                    (r - b, &SMITS_RED),
+                ]
+            }
+        } else if r <= g {
+            [
+                (b, &SMITS_WHITE),
+                (r - b, &SMITS_YELLOW),
+                (g - r, &SMITS_GREEN),
+            ]
+        } else {
+            [
+                (b, &SMITS_WHITE),
+                (g - b, &SMITS_YELLOW),
+                (r - g, &SMITS_RED),
+            ]
+        };
+        SampledSpectrum::from_fn(wavelengths, |lambda| {
+            let bin = ((lambda - 380.) / 34.).clamp(0., 9.) as usize;
+            terms
+                .iter()
+                .map(|(weight, table)| weight * table[bin])
+                .sum()
+        })
+    }
+
+    /// upsamples an RGB emission: its reflectance lit by D65, the white of sRGB
+    pub(crate) fn illuminant(rgb: &RRgb, wavelengths: &SampledWavelengths) -> Self {
+        let [r, g, b] = rgb.to_array();
+        let scale = r.max(g).max(b);
+        if scale <= 0. {
+            return SampledSpectrum::default();
+        }
+        let reflectance =
+            SampledSpectrum::reflectance(&RRgb::new(r / scale, g / scale, b / scale), wavelengths);
+        let d65 = Spectrum::Illuminant(Illuminant::D65);
+        let d65_luminance = *D65_LUMINANCE.get_or_init(|| d65.luminance());
+        reflectance
+            * SampledSpectrum::from_fn(wavelengths, |lambda| {
+                scale * d65.value(lambda) / d65_luminance
+            })
+    }
+
+    /// converts to the linear RGB of the film, through the CIE XYZ tristimulus values
+    pub(crate) fn to_rgb(&self, wavelengths: &SampledWavelengths) -> RRgb {
+        let mut xyz = [0f64; 3];
+        for i in 0..WAVELENGTH_COUNT {
+            if wavelengths.pdf[i] == 0. {
+                continue;
+            }
+            let lambda = wavelengths.lambda[i];
+            let value = self.0[i] / (wavelengths.pdf[i] * WAVELENGTH_COUNT as f64);
+            xyz[0] += cie_x(lambda) * value;
+            xyz[1] += cie_y(lambda) * value;
+            xyz[2] += cie_z(lambda) * value;
+        }
+        xyz_to_rgb(xyz.map(|v| v / CIE_Y_INTEGRAL))
+    }
+}
+
+static D65_LUMINANCE: OnceLock<f64> = OnceLock::new();
+
+/// Light traced along a path: RGB, or a spectrum sampled at the wavelengths of the path.
+pub(crate) trait Radiance:
+    Clone + ops::Add<Output = Self> + ops::Mul<Output = Self> + ops::Mul<RT, Output = Self>
+{
+    /// what the conversions from RGB need: nothing, or the wavelengths of the path
+    type Context;
+
+    fn black() -> Self;
+
+    /// reflectance or transmittance of a surface given in RGB
+    fn from_attenuation(attenuation: &RRgb, context: &Self::Context) -> Self;
+
+    fn from_emission<E: Emitter>(emitter: &E, ray_hit: &RayHit, context: &Self::Context) -> Self;
+
+    /// uniform emission of a light that is not hit, like punctual lights
+    fn from_light(light: &Light, context: &Self::Context) -> Self;
+
+    /// wavelengths of a spectral path, for the scatterings depending on the wavelength
+    fn wavelengths(context: &mut Self::Context) -> Option<&mut SampledWavelengths>;
+}
+
+impl Radiance for RRgb {
+    type Context = ();
+
+    fn black() -> Self {
+        RRgb::default()
+    }
+
+    fn from_attenuation(attenuation: &RRgb, _context: &()) -> Self {
+        attenuation.clone()
+    }
+
+    fn from_emission<E: Emitter>(emitter: &E, ray_hit: &RayHit, _context: &()) -> Self {
+        emitter.emit(ray_hit)
+    }
+
+    fn from_light(light: &Light, _context: &()) -> Self {
+        light.emitted.clone()
+    }
+
+    fn wavelengths(_context: &mut ()) -> Option<&mut SampledWavelengths> {
+        None
+    }
+}
+
+impl Radiance for SampledSpectrum {
+    type Context = SampledWavelengths;
+
+    fn black() -> Self {
+        SampledSpectrum::default()
+    }
+
+    fn from_attenuation(attenuation: &RRgb, wavelengths: &SampledWavelengths) -> Self {
+        SampledSpectrum::reflectance(attenuation, wavelengths)
+    }
+
+    fn from_emission<E: Emitter>(
+        emitter: &E,
+        ray_hit: &RayHit,
+        wavelengths: &SampledWavelengths,
+    ) -> Self {
+        emitter.emit_spectrum(ray_hit, wavelengths)
+    }
+
+    fn from_light(light: &Light, wavelengths: &SampledWavelengths) -> Self {
+        light.sample(wavelengths)
+    }
+
+    fn wavelengths(wavelengths: &mut SampledWavelengths) -> Option<&mut SampledWavelengths> {
+        Some(wavelengths)
+    }
+}
+
+/// CIE standard illuminants.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Illuminant {
+    /// incandescent lamp, 2856 K
+    A,
+    /// horizon daylight, 5003 K
+    D50,
+    /// noon daylight, 6504 K
+    D65,
+    /// equal energy
+    E,
+}
+
+/// Emission spectrum, relative power per wavelength.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Spectrum {
+    /// black body at the given temperature in kelvins
+    Blackbody(f64),
+    Illuminant(Illuminant),
+}
+
+impl FromStr for Spectrum {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            anyhow::anyhow!(
+                "invalid spectrum {}, expected a, d50, d65, e or blackbody:KELVIN",
+                s
+            )
+        };
+        match s.split_once(':') {
+            Some(("blackbody", temperature)) => match temperature.trim().parse::<f64>() {
+                Ok(temperature) if temperature > 0. => Ok(Spectrum::Blackbody(temperature)),
+                _ => Err(invalid()),
+            },
+            Some(_) => Err(invalid()),
+            None => match s {
+                "a" => Ok(Spectrum::Illuminant(Illuminant::A)),
+                "d50" => Ok(Spectrum::Illuminant(Illuminant::D50)),
+                "d65" => Ok(Spectrum::Illuminant(Illuminant::D65)),
+                "e" => Ok(Spectrum::Illuminant(Illuminant::E)),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+impl Spectrum {
+    /// relative power at `lambda` nanometers
+    pub(crate) fn value(&self, lambda: f64) -> f64 {
+        match self {
+            Spectrum::Blackbody(temperature) => {
+                // normalized to 1 at the peak wavelength (Wien's displacement law)
+                let lambda_max = 2.897_771_955e-3 / temperature * 1e9;
+                planck(lambda, *temperature) / planck(lambda_max, *temperature)
+            }
+            Spectrum::Illuminant(Illuminant::A) => {
+                let c2: f64 = 1.435e7;
+                100. * (560. / lambda).powi(5) * ((c2 / (2848. * 560.)).exp() - 1.)
+                    / ((c2 / (2848. * lambda)).exp() - 1.)
+            }
+            Spectrum::Illuminant(Illuminant::D50) => daylight(lambda, 5003.),
+            Spectrum::Illuminant(Illuminant::D65) => daylight(lambda, 6504.),
+            Spectrum::Illuminant(Illuminant::E) => 1.,
+        }
+    }
+
+    /// CIE XYZ tristimulus values, Y being the luminance
+    fn xyz(&self) -> [f64; 3] {
+        let mut xyz = [0f64; 3];
+        // 1 nm steps
+        for lambda in (LAMBDA_MIN as usize..=LAMBDA_MAX as usize).map(|l| l as f64) {
+            let value = self.value(lambda);
+            xyz[0] += cie_x(lambda) * value;
+            xyz[1] += cie_y(lambda) * value;
+            xyz[2] += cie_z(lambda) * value;
+        }
+        xyz.map(|v| v / CIE_Y_INTEGRAL)
+    }
+
+    pub(crate) fn luminance(&self) -> f64 {
+        self.xyz()[1]
+    }
+
+    /// lumens per watt of radiant power
+    ///
+    /// The power of a black body covers every wavelength, the power of the other spectra
+    /// only covers the sampled range.
+    pub(crate) fn luminous_efficacy(&self) -> f64 {
+        let power = match self {
+            Spectrum::Blackbody(temperature) => {
+                // Stefan-Boltzmann law, with the normalization of `value`, per nanometer
+                let lambda_max = 2.897_771_955e-3 / temperature * 1e9;
+                STEFAN_BOLTZMANN * temperature.powi(4) / std::f64::consts::PI * 1e9
+                    / planck(lambda_max, *temperature)
+            }
+            Spectrum::Illuminant(_) => (LAMBDA_MIN as usize..=LAMBDA_MAX as usize)
+                .map(|lambda| self.value(lambda as f64))
+                .sum(),
+        };
+        MAX_LUMINOUS_EFFICACY * self.luminance() * CIE_Y_INTEGRAL / power
+    }
+}
+
+/// Spectrum scaled to a given luminance.
+#[derive(Clone, Debug)]
+pub(crate) struct ScaledSpectrum {
+    spectrum: Spectrum,
+    scale: f64,
+}
+
+impl ScaledSpectrum {
+    pub(crate) fn new(spectrum: Spectrum, luminance: f64) -> Self {
+        ScaledSpectrum {
+            spectrum,
+            scale: luminance / spectrum.luminance(),
+        }
+    }
+
+    pub(crate) fn sample(&self, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        SampledSpectrum::from_fn(wavelengths, |lambda| {
+            self.scale * self.spectrum.value(lambda)
+        })
+    }
+
+    /// linear RGB of the spectrum, colors out of the sRGB gamut are clamped
+    pub(crate) fn to_rgb(&self) -> RRgb {
+        let [r, g, b] = xyz_to_rgb(self.spectrum.xyz().map(|v| v * self.scale)).to_array();
+        RRgb::new(r.max(0.), g.max(0.), b.max(0.))
+    }
+}
+
+/// lumens per watt at 555 nm, where the eye is the most sensitive
+const MAX_LUMINOUS_EFFICACY: f64 = 683.;
+/// W/(m^2 K^4)
+const STEFAN_BOLTZMANN: f64 = 5.670_374_419e-8;
+
+/// spectral radiance of a black body, in W/(sr m^3)
+fn planck(lambda: f64, temperature: f64) -> f64 {
+    let c = 299_792_458.;
+    let h = 6.626_070_15e-34;
+    let kb = 1.380_649e-23;
+    let l = lambda * 1e-9;
+    2. * h * c * c / (l.powi(5) * ((h * c / (l * kb * temperature)).exp() - 1.))
+}
+
+/// CIE daylight illuminant of the given correlated color temperature
+fn daylight(lambda: f64, temperature: f64) -> f64 {
+    let t = temperature;
+    let x = if t <= 7000. {
+        -4.6070e9 / t.powi(3) + 2.9678e6 / t.powi(2) + 0.09911e3 / t + 0.244_063
+    } else {
+        -2.0064e9 / t.powi(3) + 1.9018e6 / t.powi(2) + 0.24748e3 / t + 0.237_040
+    };
+    let y = -3. * x * x + 2.870 * x - 0.275;
+    let m = 0.0241 + 0.2562 * x - 0.7341 * y;
+    let m1 = (-1.3515 - 1.7703 * x + 5.9114 * y) / m;
+    let m2 = (0.0300 - 31.4424 * x + 30.0717 * y) / m;
+    // daylight basis functions, from 380 to 780 nm by 10 nm
+    let basis = |table: &[f64; 41]| {
+        let f = ((lambda - 380.) / 10.).clamp(0., 40.);
+        let i = (f as usize).min(39);
+        let a = f - i as f64;
+        table[i] * (1. - a) + table[i + 1] * a
+    };
+    basis(&DAYLIGHT_S0) + m1 * basis(&DAYLIGHT_S1) + m2 * basis(&DAYLIGHT_S2)
+}
+
+fn xyz_to_rgb([x, y, z]: [f64; 3]) -> RRgb {
+    RRgb::new(
+        3.240_454_2 * x - 1.537_138_5 * y - 0.498_531_4 * z,
+        -0.969_266 * x + 1.876_010_8 * y + 0.041_556 * z,
+        0.055_643_4 * x - 0.204_025_9 * y + 1.057_225_2 * z,
+    )
+}
+
+/// lobe of the color matching functions fit, with different widths on each side
+fn gaussian(lambda: f64, mu: f64, sigma_below: f64, sigma_above: f64) -> f64 {
+    let sigma = if lambda < mu {
+        sigma_below
+    } else {
+        sigma_above
+    };
+    (-0.5 * ((lambda - mu) / sigma).powi(2)).exp()
+}
+
+// CIE 1931 color matching functions (Wyman et al., Simple Analytic Approximations to the
+// CIE XYZ Color Matching Functions)
+fn cie_x(lambda: f64) -> f64 {
+    1.056 * gaussian(lambda, 599.8, 37.9, 31.0) + 0.362 * gaussian(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * gaussian(lambda, 501.1, 20.4, 26.2)
+}
+
+fn cie_y(lambda: f64) -> f64 {
+    0.821 * gaussian(lambda, 568.8, 46.9, 40.5) + 0.286 * gaussian(lambda, 530.9, 16.3, 31.1)
+}
+
+fn cie_z(lambda: f64) -> f64 {
+    1.217 * gaussian(lambda, 437.0, 11.8, 36.0) + 0.681 * gaussian(lambda, 459.0, 26.0, 13.8)
+}
+
+/// integral of `cie_y` over the sampled range, the luminance of a constant spectrum of 1
+const CIE_Y_INTEGRAL: f64 = 106.922;
+
+// Smits reflectance spectra, 10 bins from 380 to 720 nm
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+// CIE daylight components S0, S1 and S2, from 380 to 780 nm by 10 nm
+const DAYLIGHT_S0: [f64; 41] = [
+    63.4, 65.8, 94.8, 104.8, 105.9, 96.8, 113.9, 125.6, 125.5, 121.3, 121.3, 113.5, 113.1, 110.8,
+    106.5, 108.8, 105.3, 104.4, 100.0, 96.0, 95.1, 89.1, 90.5, 90.3, 88.4, 84.0, 85.1, 81.9, 82.6,
+    84.9, 81.3, 71.9, 74.3, 76.4, 63.3, 71.7, 77.0, 65.2, 47.7, 68.6, 65.0,
+];
+const DAYLIGHT_S1: [f64; 41] = [
+    38.5, 35.0, 43.4, 46.3, 43.9, 37.1, 36.7, 35.9, 32.6, 27.9, 24.3, 20.1, 16.2, 13.2, 8.6, 6.1,
+    4.2, 1.9, 0.0, -1.6, -3.5, -3.5, -5.8, -7.2, -8.6, -9.5, -10.9, -10.7, -12.0, -14.0, -13.6,
+    -12.0, -13.3, -12.9, -10.6, -11.6, -12.2, -10.2, -7.8, -11.2, -10.4,
+];
+const DAYLIGHT_S2: [f64; 41] = [
+    3.0, 1.2, -1.1, -0.5, -0.7, -1.2, -2.6, -2.9, -2.8, -2.6, -2.6, -1.8, -1.5, -1.3, -1.2, -1.0,
+    -0.5, -0.3, 0.0, 0.2, 0.5, 2.1, 3.2, 4.1, 4.7, 5.1, 6.7, 7.3, 8.6, 9.8, 10.2, 8.3, 9.6, 8.5,
+    7.0, 7.6, 8.0, 6.7, 5.2, 7.4, 6.8,
+];
@@ -0,0 +1,92 @@
+use crate::color::RRgb;
+use crate::ray::RT;
+use image::RgbImage;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Color varying over a surface, looked up with the texture coordinates of the point hit.
+///
+/// Colors are factors in [0, 1], they modulate what the surface emits.
+#[derive(Clone, Debug)]
+pub(crate) enum Texture {
+    /// white and black squares, `squares` along u and along v
+    Checker { squares: u32 },
+    /// image covering the whole uv square, (0, 0) being its bottom left corner
+    Image(Arc<ImageTexture>),
+}
+
+impl Texture {
+    /// color at texture coordinates `uv`, both in [0, 1]
+    pub(crate) fn value(&self, [u, v]: [RT; 2]) -> RRgb {
+        match self {
+            Texture::Checker { squares } => {
+                let square = |t: RT| (t * *squares as RT).floor() as i64;
+                if (square(u) + square(v)) % 2 == 0 {
+                    RRgb::new(1., 1., 1.)
+                } else {
+                    RRgb::new(0., 0., 0.)
+                }
+            }
+            Texture::Image(image) => image.value([u, v]),
+        }
+    }
+}
+
+impl FromStr for Texture {
+    type Err = anyhow::Error;
+
+    /// `checker:SQUARES` or `image:FILE`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("checker", squares)) => match squares.trim().parse::<u32>() {
+                Ok(squares) if squares > 0 => Ok(Texture::Checker { squares }),
+                _ => Err(anyhow::anyhow!(
+                    "invalid checker squares {}, expected an integer > 0",
+                    squares
+                )),
+            },
+            Some(("image", path)) => Ok(Texture::Image(Arc::new(ImageTexture::open(path)?))),
+            _ => Err(anyhow::anyhow!(
+                "invalid texture {}, expected checker:SQUARES or image:FILE",
+                s
+            )),
+        }
+    }
+}
+
+/// Image texture, pixel values are used as they are: 255 is a factor of 1.
+pub(crate) struct ImageTexture {
+    path: String,
+    image: RgbImage,
+}
+
+impl ImageTexture {
+    pub(crate) fn open(path: &str) -> anyhow::Result<Self> {
+        let image = image::open(path)
+            .map_err(|e| anyhow::anyhow!("cannot read texture {}: {}", path, e))?
+            .into_rgb8();
+        Ok(ImageTexture {
+            path: path.to_string(),
+            image,
+        })
+    }
+
+    /// nearest pixel of the image at `uv`
+    fn value(&self, [u, v]: [RT; 2]) -> RRgb {
+        let (width, height) = self.image.dimensions();
+        let x = ((u * width as RT) as u32).min(width - 1);
+        // image rows go downward
+        let y = (((1. - v) * height as RT) as u32).min(height - 1);
+        let [r, g, b] = self.image.get_pixel(x, y).0;
+        RRgb::new(r as f64 / 255., g as f64 / 255., b as f64 / 255.)
+    }
+}
+
+/// only describes the image, scene descriptions do not list the pixels
+impl fmt::Debug for ImageTexture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (width, height) = self.image.dimensions();
+        write!(f, "ImageTexture({}, {}x{})", self.path, width, height)
+    }
+}
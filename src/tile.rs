@@ -0,0 +1,122 @@
+use std::str::FromStr;
+
+/// Order in which the tiles are rendered.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum TileOrder {
+    /// rows of tiles, from the top of the image
+    Scanline,
+    /// from the center of the image outward
+    Spiral,
+    /// along a Hilbert curve, consecutive tiles are neighbours
+    Hilbert,
+}
+
+impl FromStr for TileOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(anyhow::anyhow!(
+                "invalid tile order {}, expected scanline, spiral or hilbert",
+                s
+            )),
+        }
+    }
+}
+
+/// Rectangular region of the film, y axis points upward.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    /// returns the tile grown by `margin` pixels on every side, clipped to the `width` x `height` image
+    pub(crate) fn expand(&self, margin: u32, width: u32, height: u32) -> Tile {
+        let x = self.x.saturating_sub(margin);
+        let y = self.y.saturating_sub(margin);
+        Tile {
+            x,
+            y,
+            width: (self.x + self.width).saturating_add(margin).min(width) - x,
+            height: (self.y + self.height).saturating_add(margin).min(height) - y,
+        }
+    }
+
+    /// pixels of the tile, row major
+    pub(crate) fn pixels(&self) -> impl Iterator<Item = (u32, u32)> {
+        let (x0, width) = (self.x, self.width);
+        (self.y..self.y + self.height).flat_map(move |y| (x0..x0 + width).map(move |x| (x, y)))
+    }
+}
+
+/// splits a `width` x `height` image into tiles of at most `tile_size` pixels wide and high,
+/// sorted in rendering order
+pub(crate) fn tiles(width: u32, height: u32, tile_size: u32, order: TileOrder) -> Vec<Tile> {
+    let tile_columns = width.div_ceil(tile_size);
+    let tile_rows = height.div_ceil(tile_size);
+    let mut grid: Vec<(u32, u32)> = (0..tile_rows)
+        .rev() // the film y axis points upward, start from the top of the image
+        .flat_map(|row| (0..tile_columns).map(move |column| (column, row)))
+        .collect();
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            let center_column = (tile_columns as f32 - 1.) / 2.;
+            let center_row = (tile_rows as f32 - 1.) / 2.;
+            let spiral_key = |&(column, row): &(u32, u32)| {
+                let dx = column as f32 - center_column;
+                let dy = row as f32 - center_row;
+                // ring around the center, then angle within the ring
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            grid.sort_by(|a, b| {
+                spiral_key(a)
+                    .partial_cmp(&spiral_key(b))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+        }
+        TileOrder::Hilbert => {
+            let size = tile_columns.max(tile_rows).next_power_of_two();
+            grid.sort_by_key(|&(column, row)| hilbert_index(size, column, row));
+        }
+    }
+    grid.into_iter()
+        .map(|(column, row)| {
+            let (x, y) = (column * tile_size, row * tile_size);
+            Tile {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            }
+        })
+        .collect()
+}
+
+/// distance of (x, y) along the Hilbert curve filling a `size` x `size` grid, `size` being a power of 2
+fn hilbert_index(size: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut index = 0u64;
+    let mut s = size / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        index += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        // rotates the quadrant so that the curve is continuous
+        if ry == 0 {
+            if rx == 1 {
+                x = size - 1 - x;
+                y = size - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}